@@ -1,116 +1,304 @@
-fn read_levels(input: &str) -> Vec<Vec<usize>> {
+use std::{error::Error, fmt};
+
+/// The reasons the reports cannot be read.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReportError {
+    InvalidNumber { line: usize, value: String },
+    TooShort { line: usize, len: usize }
+}
+
+impl fmt::Display for ReportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReportError::InvalidNumber { line, value } => write!(f, "report {line}: \"{value}\" is not a number"),
+            ReportError::TooShort { line, len } => write!(f, "report {line} has {len} levels, at least 2 are required")
+        }
+    }
+}
+
+impl Error for ReportError {}
+
+fn read_levels(input: &str) -> Result<Vec<Vec<i64>>, ReportError> {
     input
         .lines()
-        .map(|line| line
-            .split_whitespace()
-            .map(|num| num
-                .parse::<usize>()
-                .expect("Parsed string is not a number.")
-            ).collect()
-        ).collect()
-}
-
-fn is_safe(level: Vec<usize>) -> bool {
-    level
-        .windows(2)
-        .all(|window| 
-            (level[0] < level[1] && window[0] < window[1] && window[1] - window[0] <= 3)
-            || (level[0] > level[1] && window[0] > window[1] && window[0] - window[1] <= 3)
+        .zip(1..)
+        .map(|(report, line)| {
+            let level = report
+                .split_whitespace()
+                .map(|num| num
+                    .parse::<i64>()
+                    .map_err(|_| ReportError::InvalidNumber { line, value: num.to_string() })
+                ).collect::<Result<Vec<i64>, ReportError>>()?;
+
+            if level.len() < 2 {
+                return Err(ReportError::TooShort { line, len: level.len() });
+            }
+            Ok(level)
+        }).collect()
+}
+
+/// The directions a report is allowed to move in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    Increasing,
+    Decreasing,
+    Either
+}
+
+/// The rules a report has to follow to be considered safe.
+#[derive(Clone, Copy, Debug)]
+pub struct Rules {
+    pub min_step: u64,
+    pub max_step: u64,
+    pub direction: Direction,
+    pub allow_equal: bool
+}
+
+impl Default for Rules {
+    /// Strictly monotonic with steps between 1 and 3.
+    fn default() -> Self {
+        Rules {
+            min_step: 1,
+            max_step: 3,
+            direction: Direction::Either,
+            allow_equal: false
+        }
+    }
+}
+
+impl Rules {
+    /// Returns whether the report is checked as increasing, decreasing or both.
+    fn increasing(&self) -> &'static [bool] {
+        match self.direction {
+            Direction::Increasing => &[true],
+            Direction::Decreasing => &[false],
+            Direction::Either => &[true, false]
+        }
+    }
+
+    fn valid_step(&self, a: i64, b: i64, increasing: bool) -> bool {
+        if a == b {
+            return self.allow_equal;
+        }
+        // The absolute difference of two signed values always fits in a u64.
+        let step = a.abs_diff(b);
+        (a < b) == increasing && self.min_step <= step && step <= self.max_step
+    }
+}
+
+fn is_safe(level: &[i64], rules: &Rules) -> bool {
+    rules
+        .increasing()
+        .iter()
+        .any(|&increasing| level
+            .windows(2)
+            .all(|window| rules.valid_step(window[0], window[1], increasing))
         )
 }
 
-pub fn part1(input: &str) -> usize {
-    read_levels(input)
+/// Returns the indices of the fewest levels that have to be removed such that every step between
+/// the remaining levels satisfies `valid_step`.
+/// 
+/// Let best[i] be the fewest removals among the first i levels when level i is kept.
+/// Only the previous k + 1 levels need to be considered as predecessor of level i,
+/// as skipping more levels already exceeds the tolerance, making this O(n * k).
+fn min_removals(level: &[i64], k: usize, valid_step: impl Fn(i64, i64) -> bool) -> Vec<usize> {
+    let mut best = vec![0; level.len()];
+    let mut prev: Vec<Option<usize>> = vec![None; level.len()];
+    let mut min = level.len();
+    let mut last = None;
+
+    for i in 0..level.len() {
+        // Keeping level i as the first level removes all levels before it.
+        best[i] = i;
+        for j in i.saturating_sub(k + 1)..i {
+            if valid_step(level[j], level[i]) && best[j] + i - j - 1 < best[i] {
+                best[i] = best[j] + i - j - 1;
+                prev[i] = Some(j);
+            }
+        }
+        // Keeping level i as the last level removes all levels after it.
+        if best[i] + level.len() - i - 1 < min {
+            min = best[i] + level.len() - i - 1;
+            last = Some(i);
+        }
+    }
+
+    // Walk back over the kept levels to collect the removed ones.
+    let mut kept = vec![false; level.len()];
+    while let Some(i) = last {
+        kept[i] = true;
+        last = prev[i];
+    }
+    (0..level.len()).filter(|&i| !kept[i]).collect()
+}
+
+/// Returns the fewest levels to remove to make the report safe, if at most k suffice.
+fn dampen(level: &[i64], rules: &Rules, k: usize) -> Option<Vec<usize>> {
+    rules
+        .increasing()
+        .iter()
+        .map(|&increasing| min_removals(level, k, |a, b| rules.valid_step(a, b, increasing)))
+        .filter(|removed| removed.len() <= k)
+        .min_by_key(|removed| removed.len())
+}
+
+/// Tests whether the report is safe after removing at most k levels.
+fn is_safe_dampened(level: &[i64], rules: &Rules, k: usize) -> bool {
+    dampen(level, rules, k).is_some()
+}
+
+/// The ways in which a step between two levels can break the rules.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Violation {
+    DirectionFlip,
+    StepTooLarge,
+    StepTooSmall,
+    Equal
+}
+
+impl Violation {
+    /// Finds the first window of the report that breaks the rules.
+    /// When either direction is allowed, the first step that is not equal determines the direction.
+    fn find(level: &[i64], rules: &Rules) -> Option<(usize, Violation)> {
+        let increasing = match rules.direction {
+            Direction::Increasing => true,
+            Direction::Decreasing => false,
+            Direction::Either => level
+                .windows(2)
+                .find(|window| window[0] != window[1])
+                .is_none_or(|window| window[0] < window[1])
+        };
+
+        level
+            .windows(2)
+            .position(|window| !rules.valid_step(window[0], window[1], increasing))
+            .map(|i| {
+                let (a, b) = (level[i], level[i + 1]);
+                let violation = if a == b {
+                    Violation::Equal
+                } else if (a < b) != increasing {
+                    Violation::DirectionFlip
+                } else if a.abs_diff(b) > rules.max_step {
+                    Violation::StepTooLarge
+                } else {
+                    Violation::StepTooSmall
+                };
+                (i, violation)
+            })
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Violation::DirectionFlip => "direction flip",
+            Violation::StepTooLarge => "step too large",
+            Violation::StepTooSmall => "step too small",
+            Violation::Equal => "equal levels"
+        })
+    }
+}
+
+/// Explains whether a report is safe and what can be done about it if it is not.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Analysis {
+    pub level: Vec<i64>,
+    /// The index of the first level of the first violating window and how it violates the rules.
+    pub violation: Option<(usize, Violation)>,
+    /// The indices of the levels to remove to make the report safe, if at most k suffice.
+    pub removed: Option<Vec<usize>>,
+    pub k: usize
+}
+
+impl Analysis {
+    pub fn new(level: Vec<i64>, rules: &Rules, k: usize) -> Self {
+        Analysis {
+            violation: Violation::find(&level, rules),
+            removed: dampen(&level, rules, k),
+            level,
+            k
+        }
+    }
+
+    /// Whether the report is safe after removing at most k levels.
+    pub fn is_safe(&self) -> bool {
+        self.removed.is_some()
+    }
+
+    /// Whether the report is only safe because levels were removed.
+    pub fn is_dampened(&self) -> bool {
+        self.removed.as_ref().is_some_and(|removed| !removed.is_empty())
+    }
+}
+
+impl fmt::Display for Analysis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let report: Vec<String> = self.level.iter().map(|num| num.to_string()).collect();
+        write!(f, "{}: ", report.join(" "))?;
+
+        let Some((i, violation)) = self.violation else {
+            return write!(f, "safe");
+        };
+        write!(
+            f,
+            "{violation} between levels {i} and {} ({} -> {}), ",
+            i + 1,
+            self.level[i],
+            self.level[i + 1]
+        )?;
+
+        match &self.removed {
+            Some(removed) => {
+                let removed: Vec<String> = removed
+                    .iter()
+                    .map(|&j| format!("level {j} ({})", self.level[j]))
+                    .collect();
+                write!(f, "safe after removing {}", removed.join(", "))
+            },
+            None => write!(f, "unsafe even after removing {} levels", self.k)
+        }
+    }
+}
+
+/// Analyzes every report under the given rules, allowing at most k levels to be removed.
+pub fn analyze(input: &str, rules: &Rules, k: usize) -> Result<Vec<Analysis>, ReportError> {
+    Ok(read_levels(input)?
+        .into_iter()
+        .map(|level| Analysis::new(level, rules, k))
+        .collect())
+}
+
+/// Counts the reports that are safe under the given rules after removing at most k levels.
+pub fn count_safe(input: &str, rules: &Rules, k: usize) -> Result<usize, ReportError> {
+    Ok(read_levels(input)?
         .into_iter()
-        .fold(0, |acc, level| acc + is_safe(level) as usize)
+        .filter(|level| is_safe_dampened(level, rules, k))
+        .count())
+}
+
+pub fn part1(input: &str) -> usize {
+    count_safe(input, &Rules::default(), 0).expect("Reports could not be read.")
 }
 
 pub fn part2_bf(input: &str) -> usize {
+    let rules = Rules::default();
     read_levels(input)
+        .expect("Reports could not be read.")
         .into_iter()
         .fold(0, |acc, level| {
             (0..level.len())
                 .any(|i| {
                     let mut cln = level.clone();
                     cln.remove(i);
-                    is_safe(cln)
+                    is_safe(&cln, &rules)
                 }) as usize
             + acc
         })
 }
 
 pub fn part2(input: &str) -> usize {
-    read_levels(input)
-        .into_iter()
-        .fold(0, |acc, level| {
-            let mut failed = false;
-            let mut i = 0;
-
-            let mut left = level[0];
-            let mut right = level[1];
-
-            while i < level.len() - 1 {
-                if (left < right && level[i] < level[i + 1] && level[i + 1] - level[i] <= 3)
-                || (left > right && level[i] > level[i + 1] && level[i] - level[i + 1] <= 3) {
-                    i += 1;
-                } else if !failed {
-                    failed = true;
-
-                    // Test if we can omit i.
-                    if i == 0 {
-                        left = level[1];
-                        right = level[2];
-                    } else if i == 1 {
-                        right = level[2];
-                    }
-
-                    if (
-                        left < right
-                        && (i == 0 || (level[i - 1] < level[i + 1] && level[i + 1] - level[i - 1] <= 3))
-                        && (level.len() <= i + 2 || (level[i + 1] < level[i + 2] && level[i + 2] - level[i + 1] <= 3))
-                        && (level.len() <= i + 3 || (level[i + 2] < level[i + 3] && level[i + 3] - level[i + 2] <= 3))
-                    ) || (
-                        left > right
-                        && (i == 0 || (level[i - 1] > level[i + 1] && level[i - 1] - level[i + 1] <= 3))
-                        && (level.len() <= i + 2 || (level[i + 1] > level[i + 2] && level[i + 1] - level[i + 2] <= 3))
-                        && (level.len() <= i + 3 || (level[i + 2] > level[i + 3] && level[i + 2] - level[i + 3] <= 3))
-                    ) {
-                        i += 2;
-                        continue;
-                    }
-
-                    // Test if we can omit i + 1.
-                    if i == 0 {
-                        left = level[0];
-                        right = level[2];
-                    } else if i == 1 {
-                        right = level[1];
-                    }
-
-                    if (
-                        left < right
-                        && (level.len() <= i + 2 || (level[i] < level[i + 2] && level[i + 2] - level[i] <= 3))
-                        && (level.len() <= i + 3 || (level[i + 2] < level[i + 3] && level[i + 3] - level[i + 2] <= 3))
-                    ) || (
-                        left > right
-                        && (level.len() <= i + 2 || (level[i] > level[i + 2] && level[i] - level[i + 2] <= 3))
-                        && (level.len() <= i + 3 || (level[i + 2] > level[i + 3] && level[i + 2] - level[i + 3] <= 3))
-                    ) {
-                        i += 2;
-                        continue;
-                    }
-
-                    // Test if we can omit the first number to swap the direction.
-                    if i == 1 {
-                        left = level[1];
-                        right = level[2];
-                    }
-                } else {
-                    return acc;
-                }
-            }
-            acc + 1
-        })
+    count_safe(input, &Rules::default(), 1).expect("Reports could not be read.")
 }
 
 #[cfg(test)]
@@ -149,4 +337,80 @@ mod test {
         assert_eq!(part2_bf(input), 4);
         assert_eq!(part2(input), 4);
     }
+
+    #[test]
+    fn count_safe_test() {
+        let input = get_input();
+        let rules = Rules::default();
+        assert_eq!(count_safe(input, &rules, 0), Ok(2));
+        assert_eq!(count_safe(input, &rules, 1), Ok(4));
+        assert_eq!(count_safe(input, &rules, 2), Ok(6));
+        assert_eq!(count_safe("1 2 9 3 10 4 5", &rules, 1), Ok(0));
+        assert_eq!(count_safe("1 2 9 3 10 4 5", &rules, 2), Ok(1));
+    }
+
+    #[test]
+    fn rules_test() {
+        let input = get_input();
+        let decreasing = Rules { direction: Direction::Decreasing, ..Rules::default() };
+        assert_eq!(count_safe(input, &decreasing, 0), Ok(1));
+        assert_eq!(count_safe(input, &decreasing, 1), Ok(2));
+
+        let equal = Rules { allow_equal: true, ..Rules::default() };
+        assert_eq!(count_safe(input, &equal, 0), Ok(3));
+
+        let large_steps = Rules { max_step: 5, ..Rules::default() };
+        assert_eq!(count_safe(input, &large_steps, 0), Ok(4));
+        assert_eq!(count_safe(input, &large_steps, 1), Ok(6));
+
+        let min_steps = Rules { min_step: 2, ..Rules::default() };
+        assert_eq!(count_safe(input, &min_steps, 0), Ok(0));
+    }
+
+    #[test]
+    fn analyze_test() {
+        let analysis = analyze(get_input(), &Rules::default(), 1).unwrap();
+
+        assert_eq!(analysis[0].violation, None);
+        assert_eq!(analysis[0].removed, Some(vec![]));
+        assert_eq!(analysis[1].violation, Some((1, Violation::StepTooLarge)));
+        assert_eq!(analysis[1].removed, None);
+        assert_eq!(analysis[3].violation, Some((1, Violation::DirectionFlip)));
+        assert_eq!(analysis[3].removed, Some(vec![2]));
+        assert_eq!(analysis[4].violation, Some((2, Violation::Equal)));
+        assert_eq!(analysis[4].removed, Some(vec![3]));
+
+        let dampened: Vec<String> = analysis
+            .iter()
+            .filter(|analysis| analysis.is_dampened())
+            .map(|analysis| analysis.to_string())
+            .collect();
+        assert_eq!(dampened, [
+            "1 3 2 4 5: direction flip between levels 1 and 2 (3 -> 2), safe after removing level 2 (2)",
+            "8 6 4 4 1: equal levels between levels 2 and 3 (4 -> 4), safe after removing level 3 (4)"
+        ]);
+        assert_eq!(
+            analysis[2].to_string(),
+            "9 7 6 2 1: step too large between levels 2 and 3 (6 -> 2), unsafe even after removing 1 levels"
+        );
+    }
+
+    #[test]
+    fn signed_test() {
+        let rules = Rules::default();
+        assert_eq!(count_safe("-3 -1 0 2\n2 0 -3 -7\n-1 -1 -2", &rules, 0), Ok(1));
+        assert_eq!(count_safe("-3 -1 0 2\n2 0 -3 -7\n-1 -1 -2", &rules, 1), Ok(3));
+        assert_eq!(count_safe(&format!("{} {}", i64::MIN, i64::MAX), &rules, 0), Ok(0));
+    }
+
+    #[test]
+    fn invalid_reports_test() {
+        let rules = Rules::default();
+        assert_eq!(count_safe("1 2 3\n4", &rules, 1), Err(ReportError::TooShort { line: 2, len: 1 }));
+        assert_eq!(count_safe("1 2 3\n\n4 5", &rules, 1), Err(ReportError::TooShort { line: 2, len: 0 }));
+        assert_eq!(
+            count_safe("1 2 x", &rules, 1),
+            Err(ReportError::InvalidNumber { line: 1, value: "x".to_string() })
+        );
+    }
 }
\ No newline at end of file
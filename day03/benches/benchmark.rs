@@ -1,16 +1,22 @@
 use std::fs;
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 
-use day03::{part1, part2, part2_single_regex, part2_single_run};
+use day03::{part1, part2, part2_stream, scan_reader, StreamScanner};
+#[cfg(feature = "regex")]
+use day03::part2_single_regex;
 
 
 pub fn criterion_benchmark(c: &mut Criterion) {
     let input = fs::read_to_string("input.txt").unwrap();
     c.bench_function("Part 1", |b| b.iter(|| part1(black_box(&input))));
     c.bench_function("Part 2", |b| b.iter(|| part2(black_box(&input))));
-    c.bench_function("Part 2 single run", |b| b.iter(|| part2_single_run(black_box(&input))));
+    #[cfg(feature = "regex")]
     c.bench_function("Part 2 single regex", |b| b.iter(|| part2_single_regex(black_box(&input))));
+    c.bench_function("Part 2 stream", |b| b.iter(|| part2_stream(black_box(&input))));
+    c.bench_function("Part 2 stream reader", |b| b.iter(|| {
+        scan_reader(black_box(input.as_bytes()), &mut StreamScanner::new(true)).unwrap()
+    }));
 }
 
 criterion_group!(benches, criterion_benchmark);
-criterion_main!(benches);
\ No newline at end of file
+criterion_main!(benches);
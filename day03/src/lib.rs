@@ -1,138 +1,547 @@
+use std::{io::{self, Read}, ops::{Range, RangeInclusive}};
+
+use memchr::memchr2;
+#[cfg(feature = "regex")]
 use regex::Regex;
 
-pub fn part1(input: &str) -> usize {
-    let mul_regex = Regex::new(r"mul\((\d)+,(\d)+\)").unwrap();
-    mul_regex
-        .find_iter(input)
-        .fold(0, |acc, m| {
-            let vals: Vec<usize> = m
-                .as_str()[4..m.len() - 1]
-                .split(',')
-                .map(|val| val.parse::<usize>().unwrap())
-                .collect();
-            acc + vals[0] * vals[1]
-        })
+/// What executing an instruction does.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Effect {
+    /// Adds the value to the result when enabled.
+    Value(i64),
+    Enable,
+    Disable,
+    Toggle
 }
 
-pub fn part2(input: &str) -> usize {
-    let input = format!("do(){input}");
-    let input = input.as_str();
-
-    let do_regex = Regex::new(r"do\(\)").unwrap();
-    let dont_regex = Regex::new(r"don\'t\(\)").unwrap();
-    let mul_regex = Regex::new(r"mul\((\d+),(\d+)\)").unwrap();
-
-    let range_starts: Vec<usize> = do_regex.find_iter(input).map(|m| m.end()).collect();
-    let range_ends: Vec<usize> = dont_regex.find_iter(input).map(|m| m.start()).collect();
-
-    // Merge ranges and remove duplicates.
-    let mut ranges: Vec<(usize, bool)> = Vec::new();
-    let mut i = 0;
-    let mut j = 0;
-
-    while i < range_starts.len() && j < range_ends.len() {
-        if range_starts[i] < range_ends[j] {
-            ranges.push((range_starts[i], true));
-            i += 1;
-        } else {
-            ranges.push((range_ends[j], false));
-            j += 1;
-        }
-    }
-
-    ranges.extend(range_starts.iter().skip(i).map(|&start| (start, true)));
-    ranges.extend(range_ends.iter().skip(j).map(|&end| (end, false)));
-    ranges.dedup_by(|a, b| a.1 == b.1);
-
-    let mut range_index = 0;
-    mul_regex
-        .find_iter(input)
-        .map(|m| {
-            let vals: Vec<usize> = m
-                .as_str()[4..m.as_str().len() - 1]
-                .split(',')
-                .map(|val| val.parse::<usize>().unwrap())
-                .collect();
-            (m.start(), vals[0] * vals[1])
-        })
-        .filter(|&(start, _)| {
-            while range_index < ranges.len() && start > ranges[range_index].0 {
-                range_index += 1;
+type Semantics = Box<dyn Fn(&[usize]) -> Option<Effect>>;
+
+/// An instruction that can be recognised in the corrupted memory, written as name(arg,...).
+pub struct Op {
+    pub name: String,
+    pub arity: RangeInclusive<usize>,
+    /// Determines the effect of the instruction from its arguments, returning None on overflow.
+    semantics: Semantics
+}
+
+/// The instructions recognised by the lexer.
+pub struct InstructionSet {
+    ops: Vec<Op>
+}
+
+impl InstructionSet {
+    pub fn new() -> Self {
+        InstructionSet { ops: Vec::new() }
+    }
+
+    /// Registers an instruction with the number of arguments it accepts and what it does.
+    pub fn register(
+        mut self,
+        name: &str,
+        arity: RangeInclusive<usize>,
+        semantics: impl Fn(&[usize]) -> Option<Effect> + 'static
+    ) -> Self {
+        self.ops.push(Op { name: name.to_string(), arity, semantics: Box::new(semantics) });
+        self
+    }
+
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+}
+
+impl Default for InstructionSet {
+    /// The mul, do and don't instructions from the puzzle.
+    fn default() -> Self {
+        InstructionSet::new()
+            .register("mul", 2..=2, product)
+            .register("do", 0..=0, |_| Some(Effect::Enable))
+            .register("don't", 0..=0, |_| Some(Effect::Disable))
+    }
+}
+
+/// Multiplies all arguments, returning None when the product does not fit.
+pub fn product(args: &[usize]) -> Option<Effect> {
+    let product = args.iter().try_fold(1usize, |acc, &arg| acc.checked_mul(arg))?;
+    Some(Effect::Value(product.try_into().ok()?))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Instruction<'a> {
+    pub name: &'a str,
+    pub args: Vec<usize>,
+    pub effect: Effect
+}
+
+/// An instruction together with the bytes of the corrupted memory it was read from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token<'a> {
+    pub instruction: Instruction<'a>,
+    pub span: Range<usize>
+}
+
+/// The reasons an instruction whose name was recognised is rejected.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rejection {
+    /// The instruction does not follow the name(arg,...) syntax.
+    Malformed,
+    /// The instruction does not accept this number of arguments.
+    Arity,
+    /// An operand has more than 3 digits, which is only rejected in strict mode.
+    TooManyDigits,
+    /// An operand or the result of the instruction does not fit.
+    Overflow
+}
+
+/// A near-miss instruction and the offset at which it starts in the corrupted memory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rejected {
+    pub offset: usize,
+    pub rejection: Rejection
+}
+
+/// Scans the corrupted memory for the instructions of an instruction set, skipping everything else.
+pub struct Lexer<'a> {
+    input: &'a str,
+    set: &'a InstructionSet,
+    /// The bytes any instruction name can start with.
+    starts: Vec<u8>,
+    pos: usize,
+    /// Whether operands are limited to 1-3 digits as the puzzle specifies.
+    strict: bool,
+    rejected: Vec<Rejected>
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(input: &'a str, set: &'a InstructionSet) -> Self {
+        let mut starts: Vec<u8> = set.ops.iter().filter_map(|op| op.name.bytes().next()).collect();
+        starts.sort_unstable();
+        starts.dedup();
+        Lexer { input, set, starts, pos: 0, strict: false, rejected: Vec::new() }
+    }
+
+    pub fn strict(input: &'a str, set: &'a InstructionSet) -> Self {
+        Lexer { strict: true, ..Lexer::new(input, set) }
+    }
+
+    /// The near-miss instructions skipped so far.
+    pub fn rejected(&self) -> &[Rejected] {
+        &self.rejected
+    }
+
+    /// Returns the end of the digits starting at pos, if there are any.
+    fn digits(&self, pos: usize) -> Option<usize> {
+        let len = self.input.as_bytes()[pos..]
+            .iter()
+            .take_while(|c| c.is_ascii_digit())
+            .count();
+        (len > 0).then_some(pos + len)
+    }
+
+    fn number(&self, digits: Range<usize>) -> Result<usize, Rejection> {
+        if self.strict && digits.len() > 3 {
+            return Err(Rejection::TooManyDigits);
+        }
+        self.input.as_bytes()[digits]
+            .iter()
+            .try_fold(0usize, |value, &c| value.checked_mul(10)?.checked_add((c - b'0') as usize))
+            .ok_or(Rejection::Overflow)
+    }
+
+    /// Reads the arguments after the opening parenthesis at pos, returning them and the position after the closing one.
+    fn args(&self, pos: usize) -> Result<(Vec<usize>, usize), Rejection> {
+        let input = self.input.as_bytes();
+        if input.get(pos) == Some(&b')') {
+            return Ok((Vec::new(), pos + 1));
+        }
+
+        let mut digits = Vec::new();
+        let mut pos = pos;
+        loop {
+            let end = self.digits(pos).ok_or(Rejection::Malformed)?;
+            digits.push(pos..end);
+            match input.get(end) {
+                Some(b',') => pos = end + 1,
+                Some(b')') => {
+                    pos = end + 1;
+                    break;
+                },
+                _ => return Err(Rejection::Malformed)
+            }
+        }
+
+        let args = digits
+            .into_iter()
+            .map(|digits| self.number(digits))
+            .collect::<Result<Vec<usize>, Rejection>>()?;
+        Ok((args, pos))
+    }
+
+    /// Reads the instruction starting at pos, returning it and the position after it.
+    /// Returns None when no instruction name followed by an opening parenthesis starts at pos.
+    fn instruction(&self, pos: usize) -> Option<Result<(Instruction<'a>, usize), Rejection>> {
+        let rest = &self.input.as_bytes()[pos..];
+        let op = self.set.ops
+            .iter()
+            .find(|op| rest.starts_with(op.name.as_bytes()) && rest.get(op.name.len()) == Some(&b'('))?;
+
+        let name = &self.input[pos..pos + op.name.len()];
+        Some(self.args(pos + op.name.len() + 1).and_then(|(args, end)| {
+            if !op.arity.contains(&args.len()) {
+                return Err(Rejection::Arity);
             }
-            range_index > 0 && ranges[range_index - 1].1
+            let effect = (op.semantics)(&args).ok_or(Rejection::Overflow)?;
+            Ok((Instruction { name, args, effect }, end))
+        }))
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let input = self.input.as_bytes();
+        while let Some(next) = input[self.pos..].iter().position(|c| self.starts.contains(c)) {
+            let start = self.pos + next;
+            match self.instruction(start) {
+                Some(Ok((instruction, end))) => {
+                    self.pos = end;
+                    return Some(Token { instruction, span: start..end });
+                },
+                Some(Err(rejection)) => self.rejected.push(Rejected { offset: start, rejection }),
+                None => {}
+            }
+            self.pos = start + 1;
+        }
+
+        self.pos = input.len();
+        None
+    }
+}
+
+/// Scans the corrupted memory for the instructions of the default instruction set using a single regex.
+#[cfg(feature = "regex")]
+pub fn tokenize_regex(input: &str) -> Vec<Token<'_>> {
+    let regex = Regex::new(r"mul\((\d+),(\d+)\)|do\(\)|don't\(\)").unwrap();
+    regex
+        .captures_iter(input)
+        .filter_map(|caps| {
+            let m = caps.get(0).unwrap();
+            let instruction = match m.as_str() {
+                "do()" => Instruction { name: "do", args: Vec::new(), effect: Effect::Enable },
+                "don't()" => Instruction { name: "don't", args: Vec::new(), effect: Effect::Disable },
+                _ => {
+                    let args = vec![caps[1].parse().ok()?, caps[2].parse().ok()?];
+                    Instruction { name: "mul", effect: product(&args)?, args }
+                }
+            };
+            Some(Token { instruction, span: m.range() })
         })
-        .map(|(_, result)| result)
-        .sum()
+        .collect()
 }
 
-pub fn part2_single_run(input: &str) -> usize {
-    let input = format!("do(){}", input);
+/// Executes instructions, keeping track of whether values are enabled.
+pub struct Interpreter {
+    /// Whether instructions can enable and disable values.
+    conditional: bool,
+    enabled: bool,
+    /// The sum of the enabled values, which is None once it has overflowed.
+    result: Option<i64>
+}
 
-    let mul_regex = Regex::new(r"^mul\((\d+),(\d+)\)").unwrap();
+impl Interpreter {
+    pub fn new(conditional: bool) -> Self {
+        Interpreter { conditional, enabled: true, result: Some(0) }
+    }
 
-    let mut allow_mul = false;
-    let mut result = 0;
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// The sum of the enabled values, or None when it does not fit.
+    pub fn result(&self) -> Option<i64> {
+        self.result
+    }
+
+    /// Executes a single instruction, returning what it added to the result.
+    pub fn execute(&mut self, effect: Effect) -> i64 {
+        match effect {
+            Effect::Value(value) => {
+                if self.enabled {
+                    self.result = self.result.and_then(|result| result.checked_add(value));
+                    return value;
+                }
+            },
+            Effect::Enable => self.enabled = true,
+            Effect::Disable => self.enabled = !self.conditional,
+            Effect::Toggle => self.enabled = !self.enabled || !self.conditional
+        }
+        0
+    }
+
+    pub fn run<'a>(mut self, tokens: impl IntoIterator<Item = Token<'a>>) -> Option<i64> {
+        for token in tokens {
+            self.execute(token.instruction.effect);
+        }
+        self.result
+    }
+}
+
+/// Evaluates the corrupted memory with the given instruction set,
+/// returning the result, or None when it overflows, together with the rejected near-miss instructions.
+/// 
+/// In strict mode operands are limited to 1-3 digits as the puzzle specifies.
+pub fn evaluate(input: &str, set: &InstructionSet, strict: bool, conditional: bool) -> (Option<i64>, Vec<Rejected>) {
+    let mut lexer = if strict { Lexer::strict(input, set) } else { Lexer::new(input, set) };
+    let result = Interpreter::new(conditional).run(lexer.by_ref());
+    (result, lexer.rejected)
+}
+
+/// An executed instruction and what it did.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step<'a> {
+    pub token: Token<'a>,
+    /// Whether values are enabled after executing the instruction, which for values is whether they were counted.
+    pub enabled: bool,
+    pub contribution: i64
+}
+
+/// Evaluates the corrupted memory like evaluate, recording every recognised instruction.
+pub fn trace<'a>(input: &'a str, set: &'a InstructionSet, strict: bool, conditional: bool) -> Vec<Step<'a>> {
+    let lexer = if strict { Lexer::strict(input, set) } else { Lexer::new(input, set) };
+    let mut interpreter = Interpreter::new(conditional);
+    lexer
+        .map(|token| {
+            let contribution = interpreter.execute(token.instruction.effect);
+            Step { token, enabled: interpreter.is_enabled(), contribution }
+        })
+        .collect()
+}
+
+const RESET: &str = "\x1b[0m";
+const DIM: &str = "\x1b[2m";
+const COUNTED: &str = "\x1b[1;32m";
+const SKIPPED: &str = "\x1b[9;31m";
+const TOGGLE: &str = "\x1b[1;33m";
+
+/// Renders the corrupted memory with ANSI colors, dimming the disabled regions.
+/// Counted values are green, skipped values are red and struck through and toggles are yellow.
+pub fn render(input: &str, trace: &[Step]) -> String {
+    let mut rendered = String::new();
     let mut pos = 0;
+    let mut enabled = true;
+
+    for step in trace {
+        let span = step.token.span.clone();
+        if !enabled {
+            rendered.push_str(DIM);
+        }
+        rendered.push_str(&input[pos..span.start]);
+        rendered.push_str(RESET);
 
-    while let Some(next) = input[pos..].find(|c| c == 'd' || c == 'm') {
-        let match_start = pos + next;
-
-        // Test do.
-        if input[match_start..].starts_with("do()") {
-            allow_mul = true;
-            pos = match_start + 4;
-        }
-        // Test don't.
-        else if input[match_start..].starts_with(r"don't()") {
-            allow_mul = false;
-            pos = match_start + 7;
-        }
-        // Test mul.
-        else if let Some(mat) = mul_regex.find(&input[match_start..]) {
-            if allow_mul {
-                let caps = mul_regex.captures(mat.as_str()).unwrap();
-                let x: usize = caps[1].parse().unwrap();
-                let y: usize = caps[2].parse().unwrap();
-                result += x * y;
+        rendered.push_str(match step.token.instruction.effect {
+            Effect::Value(_) if step.enabled => COUNTED,
+            Effect::Value(_) => SKIPPED,
+            _ => TOGGLE
+        });
+        rendered.push_str(&input[span.clone()]);
+        rendered.push_str(RESET);
+
+        pos = span.end;
+        enabled = step.enabled;
+    }
+
+    if !enabled {
+        rendered.push_str(DIM);
+    }
+    rendered.push_str(&input[pos..]);
+    rendered.push_str(RESET);
+    rendered
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ScanState {
+    /// Looking for the next 'd' or 'm'.
+    Idle,
+    /// Matched the first bytes of the word.
+    Word(&'static [u8], usize),
+    /// Reading the first operand of a mul.
+    X,
+    /// Reading the second operand of a mul.
+    Y
+}
+
+/// Scans the corrupted memory for the default instructions without allocating,
+/// reading it in chunks such that it never has to be in memory as a whole.
+/// 
+/// Instead of buffering instructions split across chunks, the scanner is a state machine
+/// that carries its state from one chunk to the next. While no instruction is being read,
+/// memchr skips ahead to the next byte an instruction can start with.
+pub struct StreamScanner {
+    /// Whether do() and don't() enable and disable mul instructions.
+    conditional: bool,
+    /// Whether operands are limited to 1-3 digits as the puzzle specifies.
+    strict: bool,
+    state: ScanState,
+    enabled: bool,
+    x: usize,
+    /// The operand being read and how many digits it has.
+    value: usize,
+    digits: usize,
+    /// Whether the mul being read has an operand that is rejected.
+    rejected: bool,
+    /// The sum of the enabled products, which is None once it has overflowed.
+    result: Option<usize>
+}
+
+impl StreamScanner {
+    pub fn new(conditional: bool) -> Self {
+        StreamScanner {
+            conditional,
+            strict: false,
+            state: ScanState::Idle,
+            enabled: true,
+            x: 0,
+            value: 0,
+            digits: 0,
+            rejected: false,
+            result: Some(0)
+        }
+    }
+
+    pub fn strict(conditional: bool) -> Self {
+        StreamScanner { strict: true, ..StreamScanner::new(conditional) }
+    }
+
+    /// The sum of the enabled products, or None when it does not fit.
+    pub fn result(&self) -> Option<usize> {
+        self.result
+    }
+
+    fn start(&mut self, c: u8) {
+        self.state = match c {
+            b'm' => ScanState::Word(b"mul(", 1),
+            b'd' => ScanState::Word(b"do()", 1),
+            _ => ScanState::Idle
+        };
+    }
+
+    fn push_digit(&mut self, c: u8) {
+        self.digits += 1;
+        match self.value.checked_mul(10).and_then(|value| value.checked_add((c - b'0') as usize)) {
+            Some(value) => self.value = value,
+            None => self.rejected = true
+        }
+    }
+
+    /// Finishes reading an operand, returning its value.
+    fn take_number(&mut self) -> usize {
+        if self.strict && self.digits > 3 {
+            self.rejected = true;
+        }
+        self.digits = 0;
+        std::mem::take(&mut self.value)
+    }
+
+    fn mul(&mut self, y: usize) {
+        // Products that do not fit are rejected, like the lexer does.
+        if let Some(product) = self.x.checked_mul(y).filter(|&product| i64::try_from(product).is_ok()) {
+            if self.enabled {
+                self.result = self.result.and_then(|result| result.checked_add(product));
+            }
+        }
+    }
+
+    fn step(&mut self, c: u8) {
+        match self.state {
+            ScanState::Idle => self.start(c),
+            ScanState::Word(word, i) if word[i] == c => {
+                if i + 1 < word.len() {
+                    self.state = ScanState::Word(word, i + 1);
+                    return;
+                }
+                self.state = ScanState::Idle;
+                match word {
+                    b"mul(" => {
+                        self.value = 0;
+                        self.digits = 0;
+                        self.rejected = false;
+                        self.state = ScanState::X;
+                    },
+                    b"do()" => self.enabled = true,
+                    _ => self.enabled = !self.conditional
+                }
+            },
+            // "do()" and "don't()" share the same prefix.
+            ScanState::Word(b"do()", 2) if c == b'n' => self.state = ScanState::Word(b"don't()", 3),
+            ScanState::X | ScanState::Y if c.is_ascii_digit() => self.push_digit(c),
+            ScanState::X if c == b',' && self.digits > 0 => {
+                self.x = self.take_number();
+                self.state = ScanState::Y;
+            },
+            ScanState::Y if c == b')' && self.digits > 0 => {
+                let y = self.take_number();
+                if !self.rejected {
+                    self.mul(y);
+                }
+                self.state = ScanState::Idle;
+            },
+            // The instruction is malformed, but c may start the next one.
+            _ => self.start(c)
+        }
+    }
+
+    pub fn feed(&mut self, chunk: &[u8]) {
+        let mut pos = 0;
+        while pos < chunk.len() {
+            if self.state == ScanState::Idle {
+                match memchr2(b'd', b'm', &chunk[pos..]) {
+                    Some(next) => pos += next,
+                    None => return
+                }
             }
-            pos = match_start + mat.end();
-        } else {
+            self.step(chunk[pos]);
             pos += 1;
         }
     }
+}
+
+/// Streams the corrupted memory from the reader through a fixed size buffer.
+/// A result that overflows is reported as invalid data.
+pub fn scan_reader(mut reader: impl Read, scanner: &mut StreamScanner) -> io::Result<usize> {
+    let mut buffer = [0; 1 << 16];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => return scanner.result().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "result overflows")),
+            Ok(n) => scanner.feed(&buffer[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e)
+        }
+    }
+}
+
+/// The default instruction set only produces products of unsigned numbers, which cannot be negative.
+fn unsigned(result: Option<i64>) -> usize {
+    result.expect("Result overflows.").try_into().expect("Result is negative.")
+}
+
+pub fn part1(input: &str) -> usize {
+    unsigned(Interpreter::new(false).run(Lexer::new(input, &InstructionSet::default())))
+}
+
+pub fn part2(input: &str) -> usize {
+    unsigned(Interpreter::new(true).run(Lexer::new(input, &InstructionSet::default())))
+}
 
-    result
+pub fn part2_stream(input: &str) -> usize {
+    let mut scanner = StreamScanner::new(true);
+    scanner.feed(input.as_bytes());
+    scanner.result().expect("Result overflows.")
 }
 
+#[cfg(feature = "regex")]
 pub fn part2_single_regex(input: &str) -> usize {
-    let regex = Regex::new(r"(mul\((\d+),(\d+)\))|(do\(\))|(don\'t\(\))").unwrap();
-    let mut allow_mul = true;
-    regex
-        .find_iter(input)
-        .fold(0, |acc, m| {
-            match m.as_str() {
-                "do()" => {
-                    allow_mul = true;
-                    acc
-                },
-                "don't()" => {
-                    allow_mul = false;
-                    acc
-                },
-                _ => {
-                    if allow_mul {
-                        let vals: Vec<usize> = m
-                            .as_str()[4..m.len() - 1]
-                            .split(',')
-                            .map(|val| val.parse::<usize>().unwrap())
-                            .collect();
-                        acc + vals[0] * vals[1]
-                    } else {
-                        acc
-                    }
-                }
-            }
-        })
+    unsigned(Interpreter::new(true).run(tokenize_regex(input)))
 }
 
 #[cfg(test)]
@@ -146,8 +555,9 @@ mod tests {
         let input = fs::read_to_string("input.txt")?;
         dbg!(part1(&input));
         dbg!(part2(&input));
-        dbg!(part2_single_run(&input));
+        #[cfg(feature = "regex")]
         dbg!(part2_single_regex(&input));
+        dbg!(part2_stream(&input));
         Ok(())
     }
 
@@ -159,7 +569,137 @@ mod tests {
     #[test]
     fn part2_test() {
         assert_eq!(part2("xmul(2,4)&mul[3,7]!^don't()_mul(5,5)+mul(32,64](mul(11,8)undo()?mul(8,5))"), 48);
-        assert_eq!(part2_single_run("xmul(2,4)&mul[3,7]!^don't()_mul(5,5)+mul(32,64](mul(11,8)undo()?mul(8,5))"), 48);
+        #[cfg(feature = "regex")]
         assert_eq!(part2_single_regex("xmul(2,4)&mul[3,7]!^don't()_mul(5,5)+mul(32,64](mul(11,8)undo()?mul(8,5))"), 48);
+        assert_eq!(part2_stream("xmul(2,4)&mul[3,7]!^don't()_mul(5,5)+mul(32,64](mul(11,8)undo()?mul(8,5))"), 48);
+    }
+
+    fn token<'a>(name: &'a str, args: &[usize], effect: Effect, span: Range<usize>) -> Token<'a> {
+        Token { instruction: Instruction { name, args: args.to_vec(), effect }, span }
+    }
+
+    #[test]
+    fn lexer_test() {
+        let input = "xmul(2,4)&mul[3,7]!^don't()_mul(5,5)+mul(32,64](mul(11,8)undo()?mul(8,5))";
+        let set = InstructionSet::default();
+        let tokens: Vec<Token> = Lexer::new(input, &set).collect();
+        assert_eq!(tokens, [
+            token("mul", &[2, 4], Effect::Value(8), 1..9),
+            token("don't", &[], Effect::Disable, 20..27),
+            token("mul", &[5, 5], Effect::Value(25), 28..36),
+            token("mul", &[11, 8], Effect::Value(88), 48..57),
+            token("do", &[], Effect::Enable, 59..63),
+            token("mul", &[8, 5], Effect::Value(40), 64..72)
+        ]);
+        #[cfg(feature = "regex")]
+        assert_eq!(tokenize_regex(input), tokens);
+    }
+
+    #[test]
+    fn strict_test() {
+        let input = "mul(2,4)mul(1234,5)mul(12,3]mul(999,999)mul(99999999999999999999,1)";
+        let set = InstructionSet::default();
+        assert_eq!(part1(input), 2 * 4 + 1234 * 5 + 999 * 999);
+
+        let (result, rejected) = evaluate(input, &set, true, false);
+        assert_eq!(result, Some(2 * 4 + 999 * 999));
+        assert_eq!(rejected, [
+            Rejected { offset: 8, rejection: Rejection::TooManyDigits },
+            Rejected { offset: 19, rejection: Rejection::Malformed },
+            Rejected { offset: 40, rejection: Rejection::TooManyDigits }
+        ]);
+
+        let (_, rejected) = evaluate(input, &set, false, false);
+        assert_eq!(rejected, [
+            Rejected { offset: 19, rejection: Rejection::Malformed },
+            Rejected { offset: 40, rejection: Rejection::Overflow }
+        ]);
+        assert_eq!(Lexer::new("mul(4294967296,4294967296)", &set).count(), 0);
+
+        // Products that fit can still overflow the sum.
+        let input = "mul(3037000499,3037000499)".repeat(3);
+        assert_eq!(evaluate(&input, &set, false, false), (None, Vec::new()));
+        assert_eq!(evaluate(&input[..26], &set, false, false).0, Some(3037000499 * 3037000499));
+    }
+
+    #[test]
+    fn instruction_set_test() {
+        let set = InstructionSet::new()
+            .register("mul", 2..=4, product)
+            .register("add", 2..=2, |args| Some(Effect::Value((args[0] + args[1]) as i64)))
+            .register("sub", 2..=2, |args| Some(Effect::Value(args[0] as i64 - args[1] as i64)))
+            .register("flip", 0..=0, |_| Some(Effect::Toggle));
+        let input = "mul(2,3,4)add(1,2)flip()sub(100,1)mul(1)flip()sub(1,10)don't()mul(2,2)";
+
+        assert_eq!(evaluate(input, &set, false, false), (
+            Some(24 + 3 + 99 - 9 + 4),
+            vec![Rejected { offset: 34, rejection: Rejection::Arity }]
+        ));
+        assert_eq!(evaluate(input, &set, false, true).0, Some(24 + 3 - 9 + 4));
+    }
+
+    #[test]
+    fn stream_test() {
+        // Generate memory full of near-misses from a small alphabet with a linear congruential generator.
+        let alphabet = ["mul(", "do()", "don't()", "do(", "don", "mu", "m", "d", "1", "23", "4567", ",", ")", "(", "x"];
+        let mut seed: u64 = 2024;
+        let mut input = String::new();
+        for _ in 0..20000 {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            input.push_str(alphabet[(seed >> 33) as usize % alphabet.len()]);
+        }
+
+        let set = InstructionSet::default();
+        for strict in [false, true] {
+            for conditional in [false, true] {
+                let expected = evaluate(&input, &set, strict, conditional).0.unwrap();
+
+                // Chunks of every size split instructions at every possible position.
+                for chunk_size in 1..16 {
+                    let mut scanner = if strict { StreamScanner::strict(conditional) } else { StreamScanner::new(conditional) };
+                    for chunk in input.as_bytes().chunks(chunk_size) {
+                        scanner.feed(chunk);
+                    }
+                    assert_eq!(scanner.result(), Some(expected as usize));
+                }
+
+                let mut scanner = if strict { StreamScanner::strict(conditional) } else { StreamScanner::new(conditional) };
+                assert_eq!(scan_reader(input.as_bytes(), &mut scanner).unwrap() as i64, expected);
+            }
+        }
+
+        assert_eq!(part2_stream("mul(99999999999999999999,0)mul(4294967296,4294967296)mul(3,0004)"), 12);
+
+        let input = "mul(3037000499,3037000499)".repeat(3);
+        let mut scanner = StreamScanner::new(true);
+        scanner.feed(&input.as_bytes()[..52]);
+        assert_eq!(scanner.result(), Some(2 * 3037000499 * 3037000499));
+        scanner.feed(&input.as_bytes()[52..]);
+        assert_eq!(scanner.result(), None);
+        let error = scan_reader(input.as_bytes(), &mut StreamScanner::new(true)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn trace_test() {
+        let input = "xmul(2,4)&mul[3,7]!^don't()_mul(5,5)+mul(32,64](mul(11,8)undo()?mul(8,5))";
+        let set = InstructionSet::default();
+        let steps = trace(input, &set, false, true);
+
+        let summary: Vec<(usize, bool, i64)> = steps
+            .iter()
+            .map(|step| (step.token.span.start, step.enabled, step.contribution))
+            .collect();
+        assert_eq!(summary, [(1, true, 8), (20, false, 0), (28, false, 0), (48, false, 0), (59, true, 0), (64, true, 40)]);
+        assert_eq!(steps.iter().map(|step| step.contribution).sum::<i64>(), 48);
+
+        let rendered = render("amul(1,2)bdon't()cmul(3,4)do()d", &trace("amul(1,2)bdon't()cmul(3,4)do()d", &set, false, true));
+        assert_eq!(rendered, [
+            "a", RESET, COUNTED, "mul(1,2)", RESET,
+            "b", RESET, TOGGLE, "don't()", RESET,
+            DIM, "c", RESET, SKIPPED, "mul(3,4)", RESET,
+            DIM, "", RESET, TOGGLE, "do()", RESET,
+            "d", RESET
+        ].concat());
     }
 }
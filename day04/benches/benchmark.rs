@@ -1,14 +1,19 @@
 use std::fs;
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 
-use day04::{part1, part2};
+use day04::{part1, part2, read_grid, word_search, word_search_automaton, Direction};
 
 
 pub fn criterion_benchmark(c: &mut Criterion) {
     let input = fs::read_to_string("input.txt").unwrap();
     c.bench_function("Part 1", |b| b.iter(|| part1(black_box(&input))));
     c.bench_function("Part 2", |b| b.iter(|| part2(black_box(&input))));
+
+    let grid = read_grid(&input);
+    let words = ["XMAS", "SAMX", "MAS", "AXA", "MASS", "SAXAM", "XXMM", "AMAX", "SMAX", "MXAS"];
+    c.bench_function("Word search", |b| b.iter(|| word_search(black_box(&grid), &words, &Direction::ALL)));
+    c.bench_function("Word search automaton", |b| b.iter(|| word_search_automaton(black_box(&grid), &words, &Direction::ALL)));
 }
 
 criterion_group!(benches, criterion_benchmark);
-criterion_main!(benches);
\ No newline at end of file
+criterion_main!(benches);
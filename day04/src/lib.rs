@@ -1,57 +1,299 @@
-fn read_grid(input: &str) -> Vec<Vec<char>> {
+use aho_corasick::AhoCorasick;
+
+/// Reads the grid row by row. Rows do not need to have the same length.
+pub fn read_grid(input: &str) -> Vec<Vec<char>> {
     input
         .lines()
         .map(|s| s.trim().chars().collect())
         .collect()
 }
 
-pub fn part1(input: &str) -> usize {
-    let xmas = ['X', 'M', 'A', 'S'];
-    let grid = read_grid(input);
-    
-    let mut count = 0;
+/// Returns the character at (i, j), where cells past the end of a row or the grid are absent.
+fn cell(grid: &[Vec<char>], i: usize, j: usize) -> Option<char> {
+    grid.get(i)?.get(j).copied()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Right,
+    UpRight,
+    Up,
+    UpLeft,
+    Left,
+    DownLeft,
+    Down,
+    DownRight
+}
+
+impl Direction {
+    pub const ALL: [Direction; 8] = [
+        Direction::Right,
+        Direction::UpRight,
+        Direction::Up,
+        Direction::UpLeft,
+        Direction::Left,
+        Direction::DownLeft,
+        Direction::Down,
+        Direction::DownRight
+    ];
+
+    pub const ORTHOGONAL: [Direction; 4] = [Direction::Right, Direction::Up, Direction::Left, Direction::Down];
+
+    pub const DIAGONAL: [Direction; 4] = [Direction::UpRight, Direction::UpLeft, Direction::DownLeft, Direction::DownRight];
+
+    pub fn opposite(&self) -> Self {
+        match self {
+            Direction::Right => Direction::Left,
+            Direction::UpRight => Direction::DownLeft,
+            Direction::Up => Direction::Down,
+            Direction::UpLeft => Direction::DownRight,
+            Direction::Left => Direction::Right,
+            Direction::DownLeft => Direction::UpRight,
+            Direction::Down => Direction::Up,
+            Direction::DownRight => Direction::UpLeft
+        }
+    }
+
+    /// The change in row and column when taking a step in this direction.
+    fn offset(&self) -> (isize, isize) {
+        match self {
+            Direction::Right => (0, 1),
+            Direction::UpRight => (-1, 1),
+            Direction::Up => (-1, 0),
+            Direction::UpLeft => (-1, -1),
+            Direction::Left => (0, -1),
+            Direction::DownLeft => (1, -1),
+            Direction::Down => (1, 0),
+            Direction::DownRight => (1, 1)
+        }
+    }
+}
+
+/// An occurrence of a word in the grid.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Match {
+    /// The index of the word in the list of words searched for.
+    pub word: usize,
+    /// The row and column of the first letter.
+    pub start: (usize, usize),
+    pub direction: Direction
+}
+
+/// Returns the character k steps away from (i, j) in the given direction, if it is in the grid.
+fn step(grid: &[Vec<char>], (i, j): (usize, usize), direction: Direction, k: usize) -> Option<char> {
+    let (di, dj) = direction.offset();
+    let i = i.checked_add_signed(di * k as isize)?;
+    let j = j.checked_add_signed(dj * k as isize)?;
+    cell(grid, i, j)
+}
+
+/// Finds every occurrence of the words in the grid, reading in any of the given directions.
+pub fn word_search(grid: &[Vec<char>], words: &[&str], directions: &[Direction]) -> Vec<Match> {
+    let words: Vec<Vec<char>> = words.iter().map(|word| word.chars().collect()).collect();
+
+    let mut matches = Vec::new();
     for i in 0..grid.len() {
         for j in 0..grid[i].len() {
-            count +=
-            // Right.
-            (j <= grid[i].len() - 4 && (0..4).all(|k| grid[i][j + k] == xmas[k])) as usize
-            // Up right.
-            + (i >= 3 && j <= grid[i].len() - 4 && (0..4).all(|k| grid[i - k][j + k] == xmas[k])) as usize
-            // Up.
-            + (i >= 3 && (0..4).all(|k| grid[i - k][j] == xmas[k])) as usize
-            // Up Left.
-            + (i >= 3 && j >= 3 && (0..4).all(|k| grid[i - k][j - k] == xmas[k])) as usize
-            // Left.
-            + (j >= 3 && (0..4).all(|k| grid[i][j - k] == xmas[k])) as usize
-            // Down Left.
-            + (i <= grid.len() - 4 && j >= 3 && (0..4).all(|k| grid[i + k][j - k] == xmas[k])) as usize
-            // Down.
-            + (i <= grid.len() - 4 && (0..4).all(|k| grid[i + k][j] == xmas[k])) as usize
-            // Down right.
-            + (i <= grid.len() - 4 && j <= grid[i].len() - 4 && (0..4).all(|k| grid[i + k][j + k] == xmas[k])) as usize;
+            for (w, word) in words.iter().enumerate() {
+                for &direction in directions {
+                    if !word.is_empty() && word
+                        .iter()
+                        .enumerate()
+                        .all(|(k, &c)| step(grid, (i, j), direction, k) == Some(c)) {
+                        matches.push(Match { word: w, start: (i, j), direction });
+                    }
+                }
+            }
         }
     }
-    count
+    matches
 }
 
-pub fn part2(input: &str) -> usize {
-    let mas = ['M', 'A', 'S'];
-    let grid = read_grid(input);
-    
-    let mut count = 0;
-    for i in 1..grid.len()-1 {
-        for j in 1..grid[i].len()-1 {
-            // Look for MAS in each diagonal centered at grid[i][j].
-            if ((0..3).all(|k| grid[i - 1 + k][j - 1 + k] == mas[k])
-                || (0..3).all(|k| grid[i - 1 + k][j - 1 + k] == mas[2 - k]))
-            && ((0..3).all(|k| grid[i + 1 - k][j - 1 + k] == mas[k])
-                || (0..3).all(|k| grid[i + 1 - k][j - 1 + k] == mas[2 - k])) {
-                count += 1;
+/// A straight line of cells through the grid, stored as a string together with the
+/// position of the cell every byte of the string belongs to.
+struct Line {
+    text: String,
+    positions: Vec<(usize, usize)>
+}
+
+/// Extracts every maximal line of cells in the given direction.
+fn lines(grid: &[Vec<char>], direction: Direction) -> Vec<Line> {
+    let mut lines = Vec::new();
+    for i in 0..grid.len() {
+        for j in 0..grid[i].len() {
+            // Only start at cells that do not continue a line.
+            if step(grid, (i, j), direction.opposite(), 1).is_some() {
+                continue;
+            }
+
+            let mut line = Line { text: String::new(), positions: Vec::new() };
+            let (di, dj) = direction.offset();
+            let mut k = 0;
+            while let Some(c) = step(grid, (i, j), direction, k) {
+                line.text.push(c);
+                let position = ((i as isize + di * k as isize) as usize, (j as isize + dj * k as isize) as usize);
+                line.positions.extend((0..c.len_utf8()).map(|_| position));
+                k += 1;
+            }
+            lines.push(line);
+        }
+    }
+    lines
+}
+
+/// Finds the same matches as word_search, but extracts every line of the grid once and searches
+/// all words at once by running an Aho-Corasick automaton over each line forwards and backwards.
+pub fn word_search_automaton(grid: &[Vec<char>], words: &[&str], directions: &[Direction]) -> Vec<Match> {
+    // Empty words never match, but the indices of the remaining words have to be kept.
+    let (indices, words): (Vec<usize>, Vec<&str>) = words
+        .iter()
+        .copied()
+        .enumerate()
+        .filter(|(_, word)| !word.is_empty())
+        .unzip();
+    let automaton = AhoCorasick::new(&words).unwrap();
+
+    let mut matches = Vec::new();
+    for forward in [Direction::Right, Direction::Down, Direction::DownRight, Direction::DownLeft] {
+        let backward = forward.opposite();
+        if !directions.contains(&forward) && !directions.contains(&backward) {
+            continue;
+        }
+
+        for line in lines(grid, forward) {
+            if directions.contains(&forward) {
+                for m in automaton.find_overlapping_iter(&line.text) {
+                    matches.push(Match { word: indices[m.pattern()], start: line.positions[m.start()], direction: forward });
+                }
+            }
+
+            if directions.contains(&backward) {
+                let reversed: String = line.text.chars().rev().collect();
+                for m in automaton.find_overlapping_iter(&reversed) {
+                    // The first character of the match ends at m.start() in the reversed string.
+                    let start = line.positions[line.positions.len() - 1 - m.start()];
+                    matches.push(Match { word: indices[m.pattern()], start, direction: backward });
+                }
             }
         }
     }
+    matches
+}
 
-    count
+pub fn part1(input: &str) -> usize {
+    word_search(&read_grid(input), &["XMAS"], &Direction::ALL).len()
+}
+
+/// A small template grid in which None matches any character.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pattern {
+    cells: Vec<Vec<Option<char>>>
+}
+
+impl Pattern {
+    /// Reads the template like a grid, where the wildcard character matches anything.
+    /// Shorter rows are padded with wildcards.
+    pub fn new(template: &str, wildcard: char) -> Self {
+        let grid = read_grid(template);
+        let width = grid.iter().map(|row| row.len()).max().unwrap_or(0);
+        Pattern {
+            cells: grid
+                .into_iter()
+                .map(|row| (0..width)
+                    .map(|j| row.get(j).copied().filter(|&c| c != wildcard))
+                    .collect()
+                ).collect()
+        }
+    }
+
+    fn width(&self) -> usize {
+        self.cells.first().map_or(0, |row| row.len())
+    }
+
+    /// Rotates the pattern a quarter turn clockwise.
+    pub fn rotate(&self) -> Self {
+        Pattern {
+            cells: (0..self.width())
+                .map(|j| (0..self.cells.len())
+                    .rev()
+                    .map(|i| self.cells[i][j])
+                    .collect()
+                ).collect()
+        }
+    }
+
+    /// Mirrors the pattern left to right.
+    pub fn reflect(&self) -> Self {
+        Pattern {
+            cells: self.cells
+                .iter()
+                .map(|row| row.iter().rev().copied().collect())
+                .collect()
+        }
+    }
+
+    /// Returns the distinct rotations and reflections of the pattern, starting with the pattern itself.
+    pub fn orientations(&self) -> Vec<Pattern> {
+        let mut orientations: Vec<Pattern> = Vec::new();
+        for mut pattern in [self.clone(), self.reflect()] {
+            for _ in 0..4 {
+                if !orientations.contains(&pattern) {
+                    orientations.push(pattern.clone());
+                }
+                pattern = pattern.rotate();
+            }
+        }
+        orientations
+    }
+
+    /// Tests whether the pattern matches with its top left corner at (i, j).
+    fn matches(&self, grid: &[Vec<char>], (i, j): (usize, usize)) -> bool {
+        self.cells
+            .iter()
+            .enumerate()
+            .all(|(di, row)| row
+                .iter()
+                .enumerate()
+                .all(|(dj, &c)| c.is_none() || cell(grid, i + di, j + dj) == c)
+            )
+    }
+}
+
+/// An occurrence of a pattern in the grid.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PatternMatch {
+    /// The index of the orientation of the pattern that matched.
+    pub orientation: usize,
+    /// The row and column of the top left corner of the pattern.
+    pub start: (usize, usize)
+}
+
+/// Finds every occurrence of the pattern in the grid, optionally in all of its rotations and reflections.
+/// 
+/// The pattern has to fit within the rows of the grid and the width of its widest row,
+/// where wildcards also match the cells missing from shorter rows.
+pub fn find_pattern(grid: &[Vec<char>], pattern: &Pattern, all_orientations: bool) -> Vec<PatternMatch> {
+    let orientations = if all_orientations { pattern.orientations() } else { vec![pattern.clone()] };
+    let width = grid.iter().map(|row| row.len()).max().unwrap_or(0);
+
+    let mut matches = Vec::new();
+    for (orientation, pattern) in orientations.iter().enumerate() {
+        for i in 0..(grid.len() + 1).saturating_sub(pattern.cells.len()) {
+            for j in 0..(width + 1).saturating_sub(pattern.width()) {
+                if pattern.matches(grid, (i, j)) {
+                    matches.push(PatternMatch { orientation, start: (i, j) });
+                }
+            }
+        }
+    }
+    matches
+}
+
+pub fn part2(input: &str) -> usize {
+    let x_mas = Pattern::new("M.S
+    .A.
+    M.S", '.');
+    find_pattern(&read_grid(input), &x_mas, true).len()
 }
 
 #[cfg(test)]
@@ -90,4 +332,170 @@ mod tests {
     fn part2_test() {
         assert_eq!(part2(get_input()), 9);
     }
+
+    #[test]
+    fn word_search_test() {
+        let grid = read_grid(get_input());
+        assert_eq!(word_search(&grid, &["XMAS"], &Direction::ORTHOGONAL).len(), 8);
+        assert_eq!(word_search(&grid, &["XMAS"], &Direction::DIAGONAL).len(), 10);
+        assert_eq!(word_search(&grid, &["XMAS", "SAMX"], &[Direction::Right]).len(), 5);
+
+        let grid = read_grid("ABC
+        DEF");
+        assert_eq!(word_search(&grid, &["AE", "FC", "CEG"], &Direction::ALL), [
+            Match { word: 0, start: (0, 0), direction: Direction::DownRight },
+            Match { word: 1, start: (1, 2), direction: Direction::Up }
+        ]);
+    }
+
+    #[test]
+    fn pattern_test() {
+        let pattern = Pattern::new("AB
+        C", '.');
+        assert_eq!(pattern.rotate(), Pattern::new("CA
+        .B", '.'));
+        assert_eq!(pattern.reflect(), Pattern::new("BA
+        .C", '.'));
+        assert_eq!(pattern.orientations().len(), 8);
+        assert_eq!(Pattern::new("M.S\n.A.\nM.S", '.').orientations().len(), 4);
+        assert_eq!(Pattern::new(".X.\nXXX\n.X.", '.').orientations().len(), 1);
+    }
+
+    #[test]
+    fn find_pattern_test() {
+        let grid = read_grid(get_input());
+
+        let plus = Pattern::new(".M.
+        MAM
+        .M.", '.');
+        assert_eq!(find_pattern(&grid, &plus, true), []);
+
+        let square = Pattern::new("AA
+        MM", '.');
+        assert_eq!(find_pattern(&grid, &square, false), [
+            PatternMatch { orientation: 0, start: (7, 7) },
+            PatternMatch { orientation: 0, start: (7, 8) }
+        ]);
+        assert_eq!(find_pattern(&grid, &square, true).len(), 3);
+
+        let diagonal = Pattern::new("X...
+        .M..
+        ..A.
+        ...S", '.');
+        assert_eq!(find_pattern(&grid, &diagonal, true).len(), 10);
+
+        let corner = Pattern::new("XM
+        M", '.');
+        assert_eq!(find_pattern(&grid, &corner, false), [PatternMatch { orientation: 0, start: (0, 5) }]);
+    }
+
+    #[test]
+    fn word_search_automaton_test() {
+        let words = ["XMAS", "", "SAMX", "MAS", "A", "AMXS", "MMMM", "XX"];
+        let grids = [
+            read_grid(get_input()),
+            read_grid("ÄBC\nDÉF\nGHÏ"),
+            vec![]
+        ];
+        for grid in grids {
+            for directions in [&Direction::ALL[..], &Direction::ORTHOGONAL, &Direction::DIAGONAL, &[Direction::Left]] {
+                let key = |m: &Match| (m.start, m.word, m.direction as usize);
+                let mut expected = word_search(&grid, &words, directions);
+                let mut matches = word_search_automaton(&grid, &words, directions);
+                expected.sort_by_key(key);
+                matches.sort_by_key(key);
+                assert_eq!(matches, expected);
+            }
+        }
+
+        let grid = read_grid("ÄBC\nDÉF\nGHÏ");
+        let key = |m: &Match| (m.start, m.word, m.direction as usize);
+        let mut matches = word_search_automaton(&grid, &["ÏÉ", "CÉ"], &Direction::ALL);
+        matches.sort_by_key(key);
+        assert_eq!(matches, [
+            Match { word: 1, start: (0, 2), direction: Direction::DownLeft },
+            Match { word: 0, start: (2, 2), direction: Direction::UpLeft }
+        ]);
+    }
+
+    #[test]
+    fn small_grids_test() {
+        for input in ["", "\n\n", "X", "XMA", "XM\nMA", "M.S\n.A."] {
+            assert_eq!(part1(input), 0);
+            assert_eq!(part2(input), 0);
+        }
+        assert_eq!(part1("XMAS"), 1);
+        assert_eq!(part1("X\nM\nA\nS"), 1);
+        assert_eq!(part2("M.S\n.A.\nM.S"), 1);
+    }
+
+    #[test]
+    fn ragged_grids_test() {
+        let input = "XMAS
+        M
+        AMAS
+        SAMXMAS";
+        let grid = read_grid(input);
+        assert_eq!(part1(input), 4);
+        assert_eq!(word_search_automaton(&grid, &["XMAS"], &Direction::ALL).len(), 4);
+        assert_eq!(word_search(&grid, &["MAMM"], &Direction::ALL), []);
+
+        // The wildcards of the X are allowed to fall outside of the shorter rows.
+        assert_eq!(part2("M.S
+        .A
+        M.S"), 1);
+        assert_eq!(part2("MXS
+        A
+        M.S"), 0);
+        assert_eq!(part2("MAS
+        
+        MAS"), 0);
+
+        // The top left corner of the pattern is a wildcard past the end of the first row.
+        assert_eq!(
+            find_pattern(&read_grid("A\nBCD"), &Pattern::new("..\n.D", '.'), false),
+            [PatternMatch { orientation: 0, start: (0, 1) }]
+        );
+        assert_eq!(
+            find_pattern(&read_grid("\nBCD"), &Pattern::new(".\nB", '.'), false),
+            [PatternMatch { orientation: 0, start: (0, 0) }]
+        );
+
+        // Wildcards do not extend the pattern past any edge of the grid.
+        let grid = read_grid("A");
+        assert_eq!(find_pattern(&grid, &Pattern::new("A.", '.'), false), []);
+        assert_eq!(find_pattern(&grid, &Pattern::new(".A", '.'), false), []);
+        assert_eq!(find_pattern(&grid, &Pattern::new("A.", '.'), true), []);
+        assert_eq!(find_pattern(&grid, &Pattern::new("A", '.'), true).len(), 1);
+    }
+
+    #[test]
+    fn mirrored_pattern_test() {
+        let patterns = ["M.S\n.A.\nM.S", "X.\n.M", ".X\nM.", "XM", "X\n.\nA", "MA.\n..S"];
+
+        // Mirroring the grid left to right matches the mirrored patterns equally often.
+        let grid = read_grid(get_input());
+        let mirrored: Vec<Vec<char>> = grid.iter().map(|row| row.iter().rev().copied().collect()).collect();
+        for pattern in patterns {
+            let pattern = Pattern::new(pattern, '.');
+            assert_eq!(
+                find_pattern(&grid, &pattern, false).len(),
+                find_pattern(&mirrored, &pattern.reflect(), false).len(),
+                "{pattern:?}"
+            );
+        }
+
+        // Mirroring a ragged grid top to bottom keeps its rows intact.
+        let grid = read_grid("XMAS\nM\nAMAS\nSAMXMAS\n\nXM.X");
+        let mirrored: Vec<Vec<char>> = grid.iter().rev().cloned().collect();
+        for pattern in patterns {
+            let pattern = Pattern::new(pattern, '.');
+            let flipped = pattern.rotate().rotate().reflect();
+            assert_eq!(
+                find_pattern(&grid, &pattern, false).len(),
+                find_pattern(&mirrored, &flipped, false).len(),
+                "{pattern:?}"
+            );
+        }
+    }
 }
@@ -1,6 +1,6 @@
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::{cmp::Ordering, collections::{HashMap, HashSet, VecDeque}, error::Error, fmt};
 
-fn read_input(input: &str) -> (Vec<(usize, usize)>, Vec<Vec<usize>>) {
+pub fn read_input(input: &str) -> (RuleSet, Vec<Vec<usize>>) {
     let input: Vec<&str> = input
         .lines()
         .map(|line| line.trim())
@@ -25,7 +25,7 @@ fn read_input(input: &str) -> (Vec<(usize, usize)>, Vec<Vec<usize>>) {
             .collect()
         ).collect();
     
-    (rules, updates)
+    (RuleSet::new(&rules), updates)
 }
 
 fn update_subgraph(rules: &[(usize, usize)], update: &[usize]) -> Vec<(usize, usize)> {
@@ -37,89 +37,407 @@ fn update_subgraph(rules: &[(usize, usize)], update: &[usize]) -> Vec<(usize, us
         .collect()
 }
 
-fn get_order(update: &[usize]) -> HashMap<usize, usize> {
-    update
-        .iter()
-        .copied()
-        .enumerate()
-        .map(|(i, num)| (num ,i))
-        .collect()
+/// The page ordering rules, where a rule (x, y) means that page x has to be printed before page y.
+#[derive(Clone, Debug)]
+pub struct RuleSet {
+    rules: Vec<(usize, usize)>,
+    successors: HashMap<usize, HashSet<usize>>
 }
 
-fn is_sorted(rules: &[(usize, usize)], order: &HashMap<usize, usize>) -> bool {
-    rules.iter().all(|(s, t)| order[s] < order[t])
-}
+impl RuleSet {
+    pub fn new(rules: &[(usize, usize)]) -> Self {
+        RuleSet {
+            rules: rules.to_vec(),
+            successors: rules
+                .iter()
+                .fold(HashMap::new(), |mut map, rule| {
+                    map.entry(rule.0).or_default().insert(rule.1);
+                    map
+                })
+        }
+    }
 
-pub fn part1(input: &str) -> usize {
-    let (rules, updates) = read_input(input);
-    updates
-        .into_iter()
-        .fold(0, |acc, update| {
-            let order = get_order(&update);
-            if is_sorted(&update_subgraph(&rules, &update), &order) {
-                acc + update[update.len() / 2]
-            } else {
-                acc
-            }
-        })
-}
+    /// Adds the rule, returning whether it was new.
+    pub fn insert(&mut self, (x, y): (usize, usize)) -> bool {
+        let new = self.successors.entry(x).or_default().insert(y);
+        if new {
+            self.rules.push((x, y));
+        }
+        new
+    }
 
-/// Topological sort from wikipedia.
-fn center_of_sorted_update(rules: &[(usize, usize)], center_index: usize) -> usize {
-    let mut outgoing: HashMap<usize, HashSet<usize>> = rules
-        .iter()
-        .fold(HashMap::new(), |mut map, rule| {
-            map.entry(rule.0).or_default().insert(rule.1);
-            map
-        });
+    /// Removes the rule, returning whether it existed.
+    pub fn remove(&mut self, (x, y): (usize, usize)) -> bool {
+        let existed = self.successors.get_mut(&x).is_some_and(|successors| successors.remove(&y));
+        if existed {
+            self.rules.retain(|&rule| rule != (x, y));
+        }
+        existed
+    }
 
-    let mut incoming: HashMap<usize, HashSet<usize>> = rules
-        .iter()
-        .fold(HashMap::new(), |mut map, rule| {
-            map.entry(rule.1).or_default().insert(rule.0);
-            map
-        });
+    pub fn rules(&self) -> &[(usize, usize)] {
+        &self.rules
+    }
+
+    /// The rules between the pages of the update.
+    pub fn subgraph(&self, update: &[usize]) -> Vec<(usize, usize)> {
+        update_subgraph(&self.rules, update)
+    }
+
+    /// Whether there is a rule stating that page x has to be printed before page y.
+    pub fn precedes(&self, x: usize, y: usize) -> bool {
+        self.successors.get(&x).is_some_and(|successors| successors.contains(&y))
+    }
+
+    /// Compares two pages, returning None when no rule relates them.
+    pub fn compare(&self, x: usize, y: usize) -> Option<Ordering> {
+        if x == y {
+            Some(Ordering::Equal)
+        } else if self.precedes(x, y) {
+            Some(Ordering::Less)
+        } else if self.precedes(y, x) {
+            Some(Ordering::Greater)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the first rule the update violates, found by pairing each page with the pages after it.
+    pub fn first_violation(&self, update: &[usize]) -> Option<(usize, usize)> {
+        update
+            .iter()
+            .enumerate()
+            .find_map(|(i, &x)| update[i + 1..]
+                .iter()
+                .find(|&&y| self.precedes(y, x))
+                .map(|&y| (y, x))
+            )
+    }
+
+    pub fn is_sorted(&self, update: &[usize]) -> bool {
+        self.first_violation(update).is_none()
+    }
 
-    let (sources, targets): (HashSet<usize>, HashSet<usize>) = rules.iter().copied().unzip();
-    let mut queue: VecDeque<usize> = sources.difference(&targets).copied().collect();
+    /// Exports all rules as a Graphviz DOT graph.
+    pub fn to_dot(&self) -> String {
+        to_dot("rules", &[], &self.rules, |_| false)
+    }
+
+    /// Exports the rules between the pages of the update as a Graphviz DOT graph,
+    /// coloring the rules the update violates red.
+    pub fn update_to_dot(&self, update: &[usize]) -> String {
+        let position: HashMap<usize, usize> = update.iter().enumerate().map(|(i, &page)| (page, i)).collect();
+        to_dot("update", update, &self.subgraph(update), |(x, y)| position[&x] > position[&y])
+    }
+
+    /// Follows the rules backwards from a page until a page repeats, returning the pages of the cycle found.
+    /// Finds a cycle whenever every remaining page has a rule coming in from another remaining page.
+    fn find_cycle(&self, remaining: &[usize]) -> Option<Vec<usize>> {
+        let mut path = vec![*remaining.first()?];
+        loop {
+            let page = path[path.len() - 1];
+            let previous = *remaining.iter().find(|&&previous| self.precedes(previous, page))?;
+            if let Some(start) = path.iter().position(|&p| p == previous) {
+                let mut cycle = path.split_off(start);
+                cycle.reverse();
+                return Some(cycle);
+            }
+            path.push(previous);
+        }
+    }
 
-    let mut sorted_update: Vec<usize> = Vec::new();
+    /// Topological sort from wikipedia.
+    /// Fails when a page is repeated, when the rules contain a cycle,
+    /// or when the first unique_len pages are not uniquely determined.
+    fn topological_sort(&self, update: &[usize], unique_len: usize) -> Result<Vec<usize>, OrderError> {
+        let mut pages = HashSet::new();
+        if let Some(&page) = update.iter().find(|&&page| !pages.insert(page)) {
+            return Err(OrderError::Repeated(page));
+        }
 
-    while let Some(page) = queue.pop_front() {
-        sorted_update.push(page);
-        if sorted_update.len() - 1 == center_index {
-            return *sorted_update.last().unwrap();
+        let outgoing: HashMap<usize, Vec<usize>> = update
+            .iter()
+            .map(|&page| (page, update.iter().copied().filter(|&next| self.precedes(page, next)).collect()))
+            .collect();
+
+        let mut incoming: HashMap<usize, usize> = update.iter().map(|&page| (page, 0)).collect();
+        for neighbor in outgoing.values().flatten() {
+            *incoming.get_mut(neighbor).unwrap() += 1;
         }
 
-        if let Some(neighbors) = outgoing.get(&page) {
-            for neighbor in neighbors.iter() {
+        let mut queue: VecDeque<usize> = update.iter().copied().filter(|page| incoming[page] == 0).collect();
+        let mut sorted_update: Vec<usize> = Vec::new();
+        let mut ambiguous = None;
+
+        while let Some(page) = queue.pop_front() {
+            // Any of the pages in the queue could be next.
+            // The sort continues such that a cycle is still reported before the ambiguity.
+            if ambiguous.is_none() && !queue.is_empty() && sorted_update.len() < unique_len {
+                ambiguous = Some(std::iter::once(page).chain(queue.iter().copied()).collect());
+            }
+            sorted_update.push(page);
+
+            for neighbor in outgoing[&page].iter() {
                 // Remove the edge from the page coming into the neighbor.
-                incoming.get_mut(neighbor).unwrap().remove(&page);
-                if incoming[neighbor].is_empty() {
+                let count = incoming.get_mut(neighbor).unwrap();
+                *count -= 1;
+                if *count == 0 {
                     queue.push_back(*neighbor);
                 }
             }
         }
-        // Remove all outgoing edges from the current page.
-        outgoing.remove(&page);
+
+        // The pages that are left all still have incoming edges, so they contain a cycle.
+        if sorted_update.len() < update.len() {
+            let remaining: Vec<usize> = update.iter().copied().filter(|page| incoming[page] > 0).collect();
+            return Err(OrderError::Cycle(self.find_cycle(&remaining).unwrap_or(remaining)));
+        }
+
+        match ambiguous {
+            Some(pages) => Err(OrderError::Ambiguous(pages)),
+            None => Ok(sorted_update)
+        }
+    }
+
+    /// Returns the update ordered according to the rules.
+    pub fn sort(&self, update: &[usize]) -> Result<Vec<usize>, OrderError> {
+        self.topological_sort(update, update.len())
+    }
+
+    /// Returns the center page of the sorted update.
+    /// The order of the pages after the center does not have to be uniquely determined.
+    pub fn center_of_sorted_update(&self, update: &[usize]) -> Result<usize, OrderError> {
+        Ok(self.topological_sort(update, update.len() / 2 + 1)?[update.len() / 2])
+    }
+}
+
+/// A batch of updates whose validity is kept up to date while rules are added and removed.
+/// 
+/// Every update keeps count of the rules it violates, and every page keeps track of the updates it is in,
+/// such that changing a rule only has to look at the updates containing both of its pages.
+#[derive(Clone, Debug)]
+pub struct UpdateBatch {
+    rules: RuleSet,
+    updates: Vec<Vec<usize>>,
+    /// The index of every page in each update.
+    positions: Vec<HashMap<usize, usize>>,
+    violations: Vec<usize>,
+    /// The indices of the updates each page is in.
+    containing: HashMap<usize, Vec<usize>>
+}
+
+impl UpdateBatch {
+    pub fn new(rules: RuleSet, updates: Vec<Vec<usize>>) -> Self {
+        let positions: Vec<HashMap<usize, usize>> = updates
+            .iter()
+            .map(|update| update.iter().enumerate().map(|(i, &page)| (page, i)).collect())
+            .collect();
+
+        let violations = updates
+            .iter()
+            .map(|update| (0..update.len())
+                .map(|i| update[i + 1..].iter().filter(|&&y| rules.precedes(y, update[i])).count())
+                .sum()
+            ).collect();
+
+        let mut containing: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (u, update) in updates.iter().enumerate() {
+            for &page in update {
+                containing.entry(page).or_default().push(u);
+            }
+        }
+
+        UpdateBatch { rules, updates, positions, violations, containing }
+    }
+
+    pub fn rules(&self) -> &RuleSet {
+        &self.rules
+    }
+
+    pub fn updates(&self) -> &[Vec<usize>] {
+        &self.updates
+    }
+
+    pub fn is_valid(&self, update: usize) -> bool {
+        self.violations[update] == 0
+    }
+
+    /// Applies the change in violations to every update the rule violates,
+    /// returning the updates that became valid or invalid together with their new validity.
+    fn apply(&mut self, (x, y): (usize, usize), added: bool) -> Vec<(usize, bool)> {
+        let mut changed = Vec::new();
+        let Some(updates) = self.containing.get(&x) else {
+            return changed;
+        };
+
+        for &u in updates {
+            if self.positions[u].get(&y).is_some_and(|&j| j < self.positions[u][&x]) {
+                let was_valid = self.violations[u] == 0;
+                if added {
+                    self.violations[u] += 1;
+                } else {
+                    self.violations[u] -= 1;
+                }
+                if was_valid != (self.violations[u] == 0) {
+                    changed.push((u, !was_valid));
+                }
+            }
+        }
+        changed
+    }
+
+    /// Adds the rule, returning the updates whose validity changed.
+    pub fn insert_rule(&mut self, rule: (usize, usize)) -> Vec<(usize, bool)> {
+        if !self.rules.insert(rule) {
+            return Vec::new();
+        }
+        self.apply(rule, true)
     }
 
-    0
+    /// Removes the rule, returning the updates whose validity changed.
+    pub fn remove_rule(&mut self, rule: (usize, usize)) -> Vec<(usize, bool)> {
+        if !self.rules.remove(rule) {
+            return Vec::new();
+        }
+        self.apply(rule, false)
+    }
+}
+
+fn to_dot(name: &str, pages: &[usize], rules: &[(usize, usize)], violated: impl Fn((usize, usize)) -> bool) -> String {
+    let mut dot = format!("digraph {name} {{\n");
+    for page in pages {
+        dot.push_str(&format!("    {page};\n"));
+    }
+    for &(x, y) in rules {
+        if violated((x, y)) {
+            dot.push_str(&format!("    {x} -> {y} [color=red];\n"));
+        } else {
+            dot.push_str(&format!("    {x} -> {y};\n"));
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// The rules as a bit matrix over the pages that appear in them, answering whether one page precedes another
+/// with two hash map lookups and a single bit.
+/// 
+/// Pages are numbered densely in the order they first appear, such that the matrix only depends
+/// on the number of distinct pages and not on how large the page numbers are.
+#[derive(Clone, Debug)]
+pub struct PrecedenceMatrix {
+    index: HashMap<usize, usize>,
+    bits: Vec<u64>
+}
+
+impl PrecedenceMatrix {
+    pub fn new(rules: &RuleSet) -> Self {
+        let mut index = HashMap::new();
+        for &(x, y) in rules.rules.iter() {
+            for page in [x, y] {
+                let next = index.len();
+                index.entry(page).or_insert(next);
+            }
+        }
+
+        let size = index.len();
+        let mut bits = vec![0; (size * size).div_ceil(64)];
+        for &(x, y) in rules.rules.iter() {
+            let bit = index[&x] * size + index[&y];
+            bits[bit / 64] |= 1 << (bit % 64);
+        }
+        PrecedenceMatrix { index, bits }
+    }
+
+    pub fn precedes(&self, x: usize, y: usize) -> bool {
+        let (Some(&x), Some(&y)) = (self.index.get(&x), self.index.get(&y)) else {
+            return false;
+        };
+        let bit = x * self.index.len() + y;
+        self.bits[bit / 64] & (1 << (bit % 64)) != 0
+    }
+
+    /// Orders pages by the rules, considering pages without a rule between them equal.
+    /// 
+    /// This is only a valid ordering for sorting when the rules totally order the pages being sorted,
+    /// which is the case for the updates in the puzzle. Otherwise use RuleSet::sort.
+    pub fn compare(&self, x: usize, y: usize) -> Ordering {
+        if self.precedes(x, y) {
+            Ordering::Less
+        } else if self.precedes(y, x) {
+            Ordering::Greater
+        } else {
+            Ordering::Equal
+        }
+    }
+
+    /// Whether the update is sorted, assuming the rules totally order its pages.
+    pub fn is_sorted(&self, update: &[usize]) -> bool {
+        update.is_sorted_by(|&x, &y| !self.precedes(y, x))
+    }
+
+    /// Returns the center page of the sorted update by partially sorting it around the center.
+    pub fn center_of_sorted_update(&self, update: &mut [usize]) -> usize {
+        *update.select_nth_unstable_by(update.len() / 2, |&x, &y| self.compare(x, y)).1
+    }
+}
+
+/// The reasons the pages of an update cannot be put in the right order.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OrderError {
+    /// The rules between the pages form a cycle, listed in the order the rules require.
+    Cycle(Vec<usize>),
+    /// No rule determines which of these pages comes next.
+    Ambiguous(Vec<usize>),
+    /// The page appears more than once in the update.
+    Repeated(usize)
+}
+
+impl fmt::Display for OrderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |pages: &[usize]| pages.iter().map(|page| page.to_string()).collect::<Vec<String>>();
+        match self {
+            OrderError::Cycle(pages) => write!(f, "the rules form a cycle: {} -> {}", join(pages).join(" -> "), pages[0]),
+            OrderError::Ambiguous(pages) => write!(f, "the order of pages {} is not determined", join(pages).join(", ")),
+            OrderError::Repeated(page) => write!(f, "page {page} appears more than once")
+        }
+    }
+}
+
+impl Error for OrderError {}
+
+pub fn part1(input: &str) -> usize {
+    let (rules, updates) = read_input(input);
+    updates
+        .into_iter()
+        .filter(|update| rules.is_sorted(update))
+        .map(|update| update[update.len() / 2])
+        .sum()
+}
+
+/// Sums the centers of the corrected updates, failing when an update cannot be corrected.
+pub fn try_part2(input: &str) -> Result<usize, OrderError> {
+    let (rules, updates) = read_input(input);
+    updates
+        .into_iter()
+        .filter(|update| !rules.is_sorted(update))
+        .map(|update| rules.center_of_sorted_update(&update))
+        .sum()
 }
 
 pub fn part2(input: &str) -> usize {
+    try_part2(input).unwrap_or_else(|e| panic!("{e}"))
+}
+
+/// Solves part 2 by sorting around the center with the rules as a comparator instead of a topological sort.
+pub fn part2_comparator(input: &str) -> usize {
     let (rules, updates) = read_input(input);
+    let matrix = PrecedenceMatrix::new(&rules);
     updates
         .into_iter()
-        .fold(0, |acc, update| {
-            let order = get_order(&update);
-            let subgraph = update_subgraph(&rules, &update);
-            if !is_sorted(&subgraph, &order) {
-                acc + center_of_sorted_update(&subgraph, update.len() / 2)
-            } else {
-                acc
-            }
-        })
+        .filter(|update| !matrix.is_sorted(update))
+        .map(|mut update| matrix.center_of_sorted_update(&mut update))
+        .sum()
 }
 
 #[cfg(test)]
@@ -133,6 +451,7 @@ mod tests {
         let input = fs::read_to_string("input.txt")?;
         dbg!(part1(&input));
         dbg!(part2(&input));
+        dbg!(part2_comparator(&input));
         Ok(())
     }
 
@@ -175,5 +494,135 @@ mod tests {
     #[test]
     fn part2_test() {
         assert_eq!(part2(get_input()), 123);
+        assert_eq!(part2_comparator(get_input()), 123);
+    }
+
+    #[test]
+    fn rule_set_test() {
+        let (rules, updates) = read_input(get_input());
+
+        assert_eq!(rules.compare(47, 53), Some(Ordering::Less));
+        assert_eq!(rules.compare(53, 47), Some(Ordering::Greater));
+        assert_eq!(rules.compare(47, 47), Some(Ordering::Equal));
+        assert_eq!(rules.compare(47, 97), Some(Ordering::Greater));
+
+        assert_eq!(rules.first_violation(&updates[0]), None);
+        assert_eq!(rules.first_violation(&updates[3]), Some((97, 75)));
+        assert_eq!(rules.first_violation(&updates[4]), Some((29, 13)));
+        assert_eq!(rules.first_violation(&updates[5]), Some((75, 13)));
+
+        assert_eq!(rules.sort(&updates[3]), Ok(vec![97, 75, 47, 61, 53]));
+        assert_eq!(rules.sort(&updates[4]), Ok(vec![61, 29, 13]));
+        assert_eq!(rules.sort(&updates[5]), Ok(vec![97, 75, 47, 29, 13]));
+
+        assert_eq!(rules.subgraph(&updates[4]), [(61, 13), (29, 13), (61, 29)]);
+
+        // Pages without any rules cannot be ordered.
+        assert_eq!(rules.sort(&[13, 1, 61]), Err(OrderError::Ambiguous(vec![1, 61])));
+    }
+
+    #[test]
+    fn order_error_test() {
+        let rules = RuleSet::new(&[(1, 2), (2, 3), (3, 4), (4, 2), (1, 5)]);
+
+        let cycle = rules.sort(&[4, 1, 3, 2]);
+        assert_eq!(cycle, Err(OrderError::Cycle(vec![2, 3, 4])));
+        assert_eq!(cycle.unwrap_err().to_string(), "the rules form a cycle: 2 -> 3 -> 4 -> 2");
+
+        let ambiguous = rules.sort(&[2, 5, 1]);
+        assert_eq!(ambiguous, Err(OrderError::Ambiguous(vec![2, 5])));
+        assert_eq!(ambiguous.unwrap_err().to_string(), "the order of pages 2, 5 is not determined");
+
+        // Only the pages up to the center have to be uniquely determined.
+        let rules = RuleSet::new(&[(1, 2), (2, 3), (3, 4), (3, 5)]);
+        assert_eq!(rules.center_of_sorted_update(&[5, 4, 3, 2, 1]), Ok(3));
+        assert_eq!(rules.sort(&[5, 4, 3, 2, 1]), Err(OrderError::Ambiguous(vec![5, 4])));
+        assert_eq!(rules.center_of_sorted_update(&[5, 4, 3, 6, 2, 1, 7]), Err(OrderError::Ambiguous(vec![6, 1, 7])));
+
+        assert_eq!(try_part2("1|2\n2|1\n\n2,1"), Err(OrderError::Cycle(vec![1, 2])));
+
+        // A cycle is reported even when unrelated pages make the order ambiguous before it.
+        let rules = RuleSet::new(&[(3, 4), (4, 3)]);
+        assert_eq!(rules.sort(&[1, 2, 3, 4]), Err(OrderError::Cycle(vec![4, 3])));
+        assert_eq!(rules.center_of_sorted_update(&[1, 2, 3, 4]), Err(OrderError::Cycle(vec![4, 3])));
+
+        // Repeated pages cannot be ordered by the rules at all.
+        assert_eq!(try_part2("1|5\n\n5,1,5"), Err(OrderError::Repeated(5)));
+        assert_eq!(OrderError::Repeated(5).to_string(), "page 5 appears more than once");
+    }
+
+    #[test]
+    fn precedence_matrix_test() {
+        let (rules, updates) = read_input(get_input());
+        let matrix = PrecedenceMatrix::new(&rules);
+
+        for &(x, y) in rules.rules() {
+            assert!(matrix.precedes(x, y));
+            assert!(!matrix.precedes(y, x));
+        }
+        assert_eq!(matrix.compare(47, 53), Ordering::Less);
+        assert_eq!(matrix.compare(53, 47), Ordering::Greater);
+        assert_eq!(matrix.compare(47, 1000), Ordering::Equal);
+
+        for update in updates {
+            let mut sorted = update.clone();
+            sorted.sort_by(|&x, &y| matrix.compare(x, y));
+            assert_eq!(Ok(sorted), rules.sort(&update));
+            assert_eq!(matrix.is_sorted(&update), rules.is_sorted(&update));
+        }
+
+        // The matrix is sized by the number of pages, not by the largest page number.
+        let matrix = PrecedenceMatrix::new(&RuleSet::new(&[(1, 2000000), (usize::MAX, 1)]));
+        assert_eq!(matrix.bits.len(), 1);
+        assert!(matrix.precedes(usize::MAX, 1) && matrix.precedes(1, 2000000) && !matrix.precedes(2000000, 1));
+        assert_eq!(part2_comparator("1|2000000\n\n2000000,1\n1,2000000"), 2000000);
+    }
+
+    #[test]
+    fn dot_test() {
+        let rules = RuleSet::new(&[(1, 2), (2, 3), (4, 5)]);
+        assert_eq!(rules.to_dot(), "digraph rules {
+    1 -> 2;
+    2 -> 3;
+    4 -> 5;
+}
+");
+        assert_eq!(rules.update_to_dot(&[3, 1, 2, 6]), "digraph update {
+    3;
+    1;
+    2;
+    6;
+    1 -> 2;
+    2 -> 3 [color=red];
+}
+");
+    }
+
+    #[test]
+    fn update_batch_test() {
+        let (rules, updates) = read_input(get_input());
+        let mut batch = UpdateBatch::new(rules, updates);
+        let valid = |batch: &UpdateBatch| (0..batch.updates().len()).map(|u| batch.is_valid(u)).collect::<Vec<bool>>();
+        assert_eq!(valid(&batch), [true, true, true, false, false, false]);
+
+        // Update 4 only violates 29|13.
+        assert_eq!(batch.remove_rule((29, 13)), [(4, true)]);
+        assert_eq!(batch.remove_rule((29, 13)), []);
+        assert_eq!(batch.insert_rule((13, 29)), [(1, false), (2, false)]);
+        assert_eq!(batch.insert_rule((13, 29)), []);
+        assert_eq!(batch.remove_rule((13, 29)), [(1, true), (2, true)]);
+        assert_eq!(batch.remove_rule((97, 75)), [(3, true)]);
+        assert_eq!(batch.insert_rule((29, 53)), [(0, false), (1, false)]);
+        assert_eq!(valid(&batch), [false, false, true, true, true, false]);
+
+        // The incremental state matches evaluating from scratch.
+        let rebuilt = UpdateBatch::new(batch.rules().clone(), batch.updates().to_vec());
+        assert_eq!(valid(&rebuilt), valid(&batch));
+        for (u, update) in batch.updates().iter().enumerate() {
+            assert_eq!(batch.rules().is_sorted(update), batch.is_valid(u));
+        }
+
+        assert_eq!(batch.remove_rule((29, 53)), [(0, true), (1, true)]);
+        assert_eq!(batch.insert_rule((97, 75)), [(3, false)]);
     }
 }
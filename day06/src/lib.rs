@@ -1,5 +1,43 @@
+use std::{error::Error, fmt};
+
+use rayon::prelude::*;
+
+/// Why a map cannot be patrolled, with lines and columns counted from 1.
+#[derive(Debug, PartialEq)]
+pub enum MapError {
+    Empty,
+    UnknownSymbol { line: usize, column: usize, symbol: char },
+    Ragged { line: usize, len: usize, expected: usize },
+    NoGuard,
+    /// The lines and columns of all guards.
+    MultipleGuards(Vec<(usize, usize)>),
+    /// The guard walks in a loop on the map as it is, so it never leaves.
+    GuardLoops
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapError::Empty => write!(f, "the map is empty"),
+            MapError::UnknownSymbol { line, column, symbol } => write!(f, "line {line}, column {column}: unknown symbol '{symbol}'"),
+            MapError::Ragged { line, len, expected } => write!(f, "line {line} has {len} cells, expected {expected} like the first line"),
+            MapError::NoGuard => write!(f, "the map has no guard"),
+            MapError::MultipleGuards(guards) => {
+                let positions: Vec<String> = guards
+                    .iter()
+                    .map(|(line, column)| format!("line {line}, column {column}"))
+                    .collect();
+                write!(f, "the map has {} guards, at {}", guards.len(), positions.join("; "))
+            },
+            MapError::GuardLoops => write!(f, "the guard walks in a loop without adding any obstacle")
+        }
+    }
+}
+
+impl Error for MapError {}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
-enum Direction {
+pub enum Direction {
     Right = 0,
     Up = 1,
     Left = 2,
@@ -17,6 +55,15 @@ impl Direction {
         }
     }
 
+    fn to_char(self) -> char {
+        match self {
+            Direction::Right => '>',
+            Direction::Up => '^',
+            Direction::Left => '<',
+            Direction::Down => 'v'
+        }
+    }
+
     fn turn(&self) -> Self {
         match self {
             Direction::Right => Direction::Down,
@@ -25,6 +72,57 @@ impl Direction {
             Direction::Down => Direction::Left,
         }
     }
+
+    fn turn_left(&self) -> Self {
+        match self {
+            Direction::Right => Direction::Up,
+            Direction::Up => Direction::Left,
+            Direction::Left => Direction::Down,
+            Direction::Down => Direction::Right,
+        }
+    }
+
+    fn turn_by(&self, turn: Turn) -> Self {
+        match turn {
+            Turn::Left => self.turn_left(),
+            Turn::Right => self.turn()
+        }
+    }
+
+    /// The next cell in this direction, or None when it would lie before the first row or column.
+    fn step(self, (i, j): (usize, usize)) -> Option<(usize, usize)> {
+        match self {
+            Direction::Right => Some((i, j + 1)),
+            Direction::Up => i.checked_sub(1).map(|i| (i, j)),
+            Direction::Left => j.checked_sub(1).map(|j| (i, j)),
+            Direction::Down => Some((i + 1, j))
+        }
+    }
+}
+
+/// Which way the guard turns when walking into an obstruction.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Turn {
+    Left,
+    #[default]
+    Right
+}
+
+/// What happens when the guard walks off the map.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Edges {
+    /// The patrol ends.
+    #[default]
+    Exit,
+    /// The guard reappears on the opposite edge.
+    Wrap
+}
+
+/// The rules the guards follow, defaulting to the ones from the puzzle.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PatrolRules {
+    pub turn: Turn,
+    pub edges: Edges
 }
 
 #[derive(Debug, PartialEq)]
@@ -35,12 +133,12 @@ enum Space {
 }
 
 impl Space {
-    fn from_char(c: char) -> Self {
+    fn from_char(c: char) -> Option<Self> {
         match c {
-            '>' | '^' | '<' | 'v' => Space::Guard,
-            '.' => Space::Empty,
-            '#' => Space::Obstruction,
-            _ => panic!("Invalid space character.")
+            '>' | '^' | '<' | 'v' => Some(Space::Guard),
+            '.' => Some(Space::Empty),
+            '#' => Some(Space::Obstruction),
+            _ => None
         }
     }
 }
@@ -50,137 +148,230 @@ enum Move {
     Turn
 }
 
-fn read_input(input: &str) -> Vec<Vec<char>> {
-    input
-        .lines()
-        .map(|line| line.trim().chars().collect())
-        .collect()
+/// The map as a bitset of obstructions, with the guards and their directions kept separately.
+struct Map {
+    height: usize,
+    width: usize,
+    obstructions: Vec<u64>,
+    guards: Vec<((usize, usize), Direction)>
+}
+
+impl Map {
+    fn blocked(&self, i: usize, j: usize) -> bool {
+        let k = i * self.width + j;
+        self.obstructions[k / 64] >> (k % 64) & 1 == 1
+    }
+
+    fn set_blocked(&mut self, i: usize, j: usize, blocked: bool) {
+        let k = i * self.width + j;
+        if blocked {
+            self.obstructions[k / 64] |= 1 << (k % 64);
+        } else {
+            self.obstructions[k / 64] &= !(1 << (k % 64));
+        }
+    }
+}
+
+/// The directions the guard has walked through each cell in, packed into four bits per cell.
+struct Visited {
+    width: usize,
+    bits: Vec<u8>
+}
+
+impl Visited {
+    fn new(height: usize, width: usize) -> Self {
+        Visited { width, bits: vec![0; (height * width).div_ceil(2)] }
+    }
+
+    fn nibble(&self, i: usize, j: usize) -> u8 {
+        let k = i * self.width + j;
+        self.bits[k / 2] >> (k % 2 * 4) & 0xF
+    }
+
+    fn get(&self, i: usize, j: usize, dir: Direction) -> bool {
+        self.nibble(i, j) >> dir as usize & 1 == 1
+    }
+
+    /// Whether the cell has been walked in any direction.
+    fn any(&self, i: usize, j: usize) -> bool {
+        self.nibble(i, j) != 0
+    }
+
+    fn set(&mut self, i: usize, j: usize, dir: Direction) {
+        let k = i * self.width + j;
+        self.bits[k / 2] |= 1 << (k % 2 * 4 + dir as usize);
+    }
+
+    fn unset(&mut self, i: usize, j: usize, dir: Direction) {
+        let k = i * self.width + j;
+        self.bits[k / 2] &= !(1 << (k % 2 * 4 + dir as usize));
+    }
+}
+
+/// Reads a rectangular map of known symbols with any number of guards.
+fn parse_map(input: &str) -> Result<Map, MapError> {
+    let rows: Vec<Vec<char>> = input.lines().map(|line| line.trim().chars().collect()).collect();
+    let (height, width) = (rows.len(), rows.first().map_or(0, |row| row.len()));
+    if rows.iter().all(|row| row.is_empty()) {
+        return Err(MapError::Empty);
+    }
+
+    let mut map = Map { height, width, obstructions: vec![0; (height * width).div_ceil(64)], guards: Vec::new() };
+    for (i, row) in rows.iter().enumerate() {
+        if row.len() != width {
+            return Err(MapError::Ragged { line: i + 1, len: row.len(), expected: width });
+        }
+        for (j, &c) in row.iter().enumerate() {
+            match Space::from_char(c) {
+                Some(Space::Guard) => map.guards.push(((i, j), Direction::from_char(c))),
+                Some(Space::Obstruction) => map.set_blocked(i, j, true),
+                Some(Space::Empty) => (),
+                None => return Err(MapError::UnknownSymbol { line: i + 1, column: j + 1, symbol: c })
+            }
+        }
+    }
+    Ok(map)
 }
 
-fn get_movement(map: &[Vec<char>], dir: &Direction, i: usize, j: usize) -> Option<Move> {
-    (if j < map[0].len() - 1 && matches!(dir, Direction::Right) {
-        Some((i, j + 1))
-    } else if i > 0 && matches!(dir, Direction::Up) {
-        Some((i - 1, j))
-    } else if j > 0 && matches!(dir, Direction::Left) {
-        Some((i, j - 1))
-    } else if i < map.len() - 1 && matches!(dir, Direction::Down) {
-        Some((i + 1, j))
+/// Reads a map with exactly one guard.
+fn read_input(input: &str) -> Result<Map, MapError> {
+    let map = parse_map(input)?;
+    match map.guards.len() {
+        0 => Err(MapError::NoGuard),
+        1 => Ok(map),
+        _ => Err(MapError::MultipleGuards(map.guards.iter().map(|&((i, j), _)| (i + 1, j + 1)).collect()))
+    }
+}
+
+fn find_guard(map: &Map) -> ((usize, usize), Direction) {
+    map.guards[0]
+}
+
+/// Moves one cell in the given direction, wrapping around or leaving the map depending on the edges.
+fn advance(height: usize, width: usize, dir: Direction, i: usize, j: usize, edges: Edges) -> Option<(usize, usize)> {
+    match edges {
+        Edges::Exit => dir.step((i, j)).filter(|&(i, j)| i < height && j < width),
+        Edges::Wrap => Some(match dir {
+            Direction::Right => (i, (j + 1) % width),
+            Direction::Up => ((i + height - 1) % height, j),
+            Direction::Left => (i, (j + width - 1) % width),
+            Direction::Down => ((i + 1) % height, j)
+        })
+    }
+}
+
+fn get_movement(map: &Map, rules: &PatrolRules, dir: &Direction, i: usize, j: usize) -> Option<Move> {
+    advance(map.height, map.width, *dir, i, j, rules.edges).map(|(i_next, j_next)| if map.blocked(i_next, j_next) {
+        Move::Turn
     } else {
-        None
-    }).map(|(i_next, j_next)| match Space::from_char(map[i_next][j_next]) {
-        Space::Obstruction => Move::Turn,
-        _ => Move::Step(i_next, j_next)
+        Move::Step(i_next, j_next)
     })
 }
 
-pub fn part1(input: &str) -> usize {
-    let map = read_input(input);
+pub fn try_part1(input: &str) -> Result<usize, MapError> {
+    let map = read_input(input)?;
+    let rules = PatrolRules::default();
 
-    let mut visited = vec![vec![false; map[0].len()]; map.len()];
-    let (mut i, mut j) = map
-        .iter()
-        .enumerate()
-        .find_map(|(i, row)| row
-            .iter()
-            .position(|c| matches!(Space::from_char(*c), Space::Guard))
-            .map(|j| (i, j)))
-        .unwrap();
+    let mut visited = Visited::new(map.height, map.width);
+    let ((mut i, mut j), mut dir) = find_guard(&map);
 
-    let mut dir = Direction::from_char(map[i][j]);
-    visited[i][j] = true;
+    visited.set(i, j, dir);
     let mut count = 1;
     
-    while let Some(movement) = get_movement(&map, &dir, i, j) {
+    while let Some(movement) = get_movement(&map, &rules, &dir, i, j) {
         match movement {
             Move::Step(i_next, j_next) => {
                 i = i_next;
                 j = j_next;
-                if !visited[i][j] {
-                    visited[i][j] = true;
+                if !visited.any(i, j) {
                     count += 1;
                 }
             },
             Move::Turn => {
-                dir = dir.turn();
+                dir = dir.turn_by(rules.turn);
             },
         }
+
+        if visited.get(i, j, dir) {
+            return Err(MapError::GuardLoops);
+        }
+        visited.set(i, j, dir);
     }
 
-    count
+    Ok(count)
 }
 
-fn find_cycle(map: &mut [Vec<char>], mut dir: Direction, mut i: usize, mut j: usize, visited: & mut[Vec<Vec<bool>>]) -> bool {
-    let (i_obstacle, j_obstacle) = match dir {
-        Direction::Right => (i, j + 1),
-        Direction::Up => (i - 1, j),
-        Direction::Left => (i, j - 1),
-        Direction::Down => (i + 1, j),
-    };
+pub fn part1(input: &str) -> usize {
+    try_part1(input).unwrap_or_else(|e| panic!("{e}"))
+}
 
+/// Tests whether the guard walks in a cycle after placing an obstacle on the cell it is about to step onto.
+fn find_cycle(
+    map: &mut Map,
+    rules: &PatrolRules,
+    mut dir: Direction,
+    (mut i, mut j): (usize, usize),
+    (i_obstacle, j_obstacle): (usize, usize),
+    visited: &mut Visited
+) -> bool {
     // The obstacle cannot be placed in a space we have already visited.
-    if visited[i_obstacle][j_obstacle].iter().any(|&vis| vis) {
+    if visited.any(i_obstacle, j_obstacle) {
         return false;
     }
 
     // Place obstacle.
-    map[i_obstacle][j_obstacle] = '#';
+    map.set_blocked(i_obstacle, j_obstacle, true);
 
     // Keep track of which positions we step during the cycle search so we may remove them when returning.
-    let mut steps: Vec<(usize, usize, usize)> = Vec::new();
+    let mut steps: Vec<(usize, usize, Direction)> = Vec::new();
 
-    while let Some(movement) = get_movement(map, &dir, i, j) {
+    while let Some(movement) = get_movement(map, rules, &dir, i, j) {
         match movement {
             Move::Step(i_next, j_next) => {
                 i = i_next;
                 j = j_next;
             },
             Move::Turn => {
-                dir = dir.turn();
+                dir = dir.turn_by(rules.turn);
             },
         }
 
-        if visited[i][j][dir as usize] {
+        if visited.get(i, j, dir) {
             // Reset old state and return cycle found.
-            map[i_obstacle][j_obstacle] = '.';
+            map.set_blocked(i_obstacle, j_obstacle, false);
             for (i, j, dir) in steps {
-                visited[i][j][dir] = false;
+                visited.unset(i, j, dir);
             }
             return true;
         }
 
-        steps.push((i, j, dir as usize));
-        visited[i][j][dir as usize] = true;
+        steps.push((i, j, dir));
+        visited.set(i, j, dir);
     }
 
     // Reset old state and return cycle not found.
-    map[i_obstacle][j_obstacle] = '.';
+    map.set_blocked(i_obstacle, j_obstacle, false);
     for (i, j, dir) in steps {
-        visited[i][j][dir] = false;
+        visited.unset(i, j, dir);
     }
     false
 }
 
-pub fn part2(input: &str) -> usize {
-    let mut map = read_input(input);
+pub fn try_part2(input: &str) -> Result<usize, MapError> {
+    let mut map = read_input(input)?;
+    let rules = PatrolRules::default();
 
-    let mut visited = vec![vec![vec![false; 4]; map[0].len()]; map.len()];
-    let (mut i, mut j) = map
-        .iter()
-        .enumerate()
-        .find_map(|(i, row)| row
-            .iter()
-            .position(|c| matches!(Space::from_char(*c), Space::Guard))
-            .map(|j| (i, j)))
-        .unwrap();
+    let mut visited = Visited::new(map.height, map.width);
+    let ((mut i, mut j), mut dir) = find_guard(&map);
 
-    let mut dir = Direction::from_char(map[i][j]);
-    visited[i][j][dir as usize] = true;
+    visited.set(i, j, dir);
     let mut count = 0;
     
-    while let Some(movement) = get_movement(&map, &dir, i, j) {
-        if !matches!(movement, Move::Turn) && find_cycle(&mut map, dir, i, j, &mut visited) {
-            count += 1;
+    while let Some(movement) = get_movement(&map, &rules, &dir, i, j) {
+        if let Move::Step(i_next, j_next) = movement {
+            if find_cycle(&mut map, &rules, dir, (i, j), (i_next, j_next), &mut visited) {
+                count += 1;
+            }
         }
 
         match movement {
@@ -189,14 +380,427 @@ pub fn part2(input: &str) -> usize {
                 j = j_next;
             },
             Move::Turn => {
-                dir = dir.turn();
+                dir = dir.turn_by(rules.turn);
             },
         }
 
-        visited[i][j][dir as usize] = true;
+        if visited.get(i, j, dir) {
+            return Err(MapError::GuardLoops);
+        }
+        visited.set(i, j, dir);
     }
 
-    count
+    Ok(count)
+}
+
+pub fn part2(input: &str) -> usize {
+    try_part2(input).unwrap_or_else(|e| panic!("{e}"))
+}
+
+/// For every cell and direction, the last cell the guard reaches before walking into an obstruction,
+/// stored as its column when walking left or right and as its row when walking up or down.
+/// 
+/// The table assumes the guard leaves the map at its edges. With wrap-around edges the guard never leaves,
+/// so every route loops and there is nothing to look up.
+struct JumpTable {
+    width: usize,
+    /// Which way the guard turns at the end of a jump.
+    turn: Turn,
+    jumps: Vec<[u32; 4]>
+}
+
+impl JumpTable {
+    /// Marks the guard walking off the map instead of into an obstruction.
+    const EXIT: u32 = u32::MAX;
+
+    fn new(map: &Map, turn: Turn) -> Self {
+        let (height, width) = (map.height, map.width);
+        let mut jumps = vec![[Self::EXIT; 4]; height * width];
+        let blocked = |i: usize, j: usize| map.blocked(i, j);
+
+        // Sweep against each direction, remembering the cell in front of the last obstruction seen.
+        for i in 0..height {
+            let mut last = Self::EXIT;
+            for j in (0..width).rev() {
+                if blocked(i, j) {
+                    last = j.checked_sub(1).map_or(Self::EXIT, |j| j as u32);
+                } else {
+                    jumps[i * width + j][Direction::Right as usize] = last;
+                }
+            }
+
+            let mut last = Self::EXIT;
+            for j in 0..width {
+                if blocked(i, j) {
+                    last = (j + 1) as u32;
+                } else {
+                    jumps[i * width + j][Direction::Left as usize] = last;
+                }
+            }
+        }
+
+        for j in 0..width {
+            let mut last = Self::EXIT;
+            for i in 0..height {
+                if blocked(i, j) {
+                    last = (i + 1) as u32;
+                } else {
+                    jumps[i * width + j][Direction::Up as usize] = last;
+                }
+            }
+
+            let mut last = Self::EXIT;
+            for i in (0..height).rev() {
+                if blocked(i, j) {
+                    last = i.checked_sub(1).map_or(Self::EXIT, |i| i as u32);
+                } else {
+                    jumps[i * width + j][Direction::Down as usize] = last;
+                }
+            }
+        }
+
+        JumpTable { width, turn, jumps }
+    }
+
+    /// Jumps from (i, j) to the last cell before the next obstruction,
+    /// which may be the extra obstruction that is not part of the table.
+    fn jump(&self, i: usize, j: usize, dir: Direction, (i_obstacle, j_obstacle): (usize, usize)) -> Option<(usize, usize)> {
+        let target = self.jumps[i * self.width + j][dir as usize];
+        let target = (target != Self::EXIT).then_some(target as usize);
+
+        // The extra obstruction is hit when it lies between the guard and the target.
+        match dir {
+            Direction::Right if i_obstacle == i && j_obstacle > j
+                && target.is_none_or(|j_target| j_obstacle <= j_target) => Some((i, j_obstacle - 1)),
+            Direction::Up if j_obstacle == j && i_obstacle < i
+                && target.is_none_or(|i_target| i_obstacle >= i_target) => Some((i_obstacle + 1, j)),
+            Direction::Left if i_obstacle == i && j_obstacle < j
+                && target.is_none_or(|j_target| j_obstacle >= j_target) => Some((i, j_obstacle + 1)),
+            Direction::Down if j_obstacle == j && i_obstacle > i
+                && target.is_none_or(|i_target| i_obstacle <= i_target) => Some((i_obstacle - 1, j)),
+            Direction::Right | Direction::Left => target.map(|j_target| (i, j_target)),
+            Direction::Up | Direction::Down => target.map(|i_target| (i_target, j))
+        }
+    }
+
+    /// Tests whether the guard walks in a cycle after placing the obstacle, jumping from turn to turn,
+    /// returning the first turn that is repeated.
+    /// The turns taken are marked in seen with the stamp, so seen does not have to be cleared between searches.
+    fn find_cycle(
+        &self,
+        mut dir: Direction,
+        (mut i, mut j): (usize, usize),
+        obstacle: (usize, usize),
+        seen: &mut [u32],
+        stamp: u32
+    ) -> Option<((usize, usize), Direction)> {
+        while let Some((i_next, j_next)) = self.jump(i, j, dir, obstacle) {
+            (i, j) = (i_next, j_next);
+            dir = dir.turn_by(self.turn);
+
+            let state = (i * self.width + j) * 4 + dir as usize;
+            if seen[state] == stamp {
+                return Some(((i, j), dir));
+            }
+            seen[state] = stamp;
+        }
+        None
+    }
+
+    /// Collects the turns of the cycle that contains the given turn.
+    fn cycle_turns(&self, start: ((usize, usize), Direction), obstacle: (usize, usize)) -> Vec<((usize, usize), Direction)> {
+        let mut turns = vec![start];
+        let ((mut i, mut j), mut dir) = start;
+        loop {
+            (i, j) = self.jump(i, j, dir, obstacle).unwrap();
+            dir = dir.turn_by(self.turn);
+            if ((i, j), dir) == start {
+                return turns;
+            }
+            turns.push(((i, j), dir));
+        }
+    }
+}
+
+/// An obstacle that can be placed on the original route, together with the direction and cell
+/// from which the guard first walks into it.
+struct Candidate {
+    dir: Direction,
+    from: (usize, usize),
+    obstacle: (usize, usize)
+}
+
+/// Walks the original route, returning a candidate for every cell except the starting one.
+fn route_candidates(map: &Map, rules: &PatrolRules) -> Result<Vec<Candidate>, MapError> {
+    let ((mut i, mut j), mut dir) = find_guard(map);
+
+    let mut visited = Visited::new(map.height, map.width);
+    visited.set(i, j, dir);
+    let mut candidates = Vec::new();
+
+    while let Some(movement) = get_movement(map, rules, &dir, i, j) {
+        match movement {
+            Move::Step(i_next, j_next) => {
+                if !visited.any(i_next, j_next) {
+                    candidates.push(Candidate { dir, from: (i, j), obstacle: (i_next, j_next) });
+                }
+                i = i_next;
+                j = j_next;
+            },
+            Move::Turn => {
+                dir = dir.turn_by(rules.turn);
+            },
+        }
+
+        if visited.get(i, j, dir) {
+            return Err(MapError::GuardLoops);
+        }
+        visited.set(i, j, dir);
+    }
+
+    Ok(candidates)
+}
+
+/// Solves part 2 by placing an obstacle on every cell of the original route, and checking for a cycle
+/// from the moment the guard would first walk into it using the jump table.
+pub fn try_part2_jump(input: &str) -> Result<usize, MapError> {
+    let map = read_input(input)?;
+    let table = JumpTable::new(&map, Turn::Right);
+
+    let mut seen = vec![0; map.height * map.width * 4];
+    Ok(route_candidates(&map, &PatrolRules::default())?
+        .into_iter()
+        .zip(1..)
+        .filter(|(candidate, stamp)| table.find_cycle(candidate.dir, candidate.from, candidate.obstacle, &mut seen, *stamp).is_some())
+        .count())
+}
+
+pub fn part2_jump(input: &str) -> usize {
+    try_part2_jump(input).unwrap_or_else(|e| panic!("{e}"))
+}
+
+/// Solves part 2 like part2_jump, but checks the candidates on the rayon thread pool,
+/// where every thread keeps its own seen states.
+pub fn try_part2_parallel(input: &str) -> Result<usize, MapError> {
+    let map = read_input(input)?;
+    let table = JumpTable::new(&map, Turn::Right);
+    let states = map.height * map.width * 4;
+
+    Ok(route_candidates(&map, &PatrolRules::default())?
+        .par_iter()
+        .map_init(
+            || (vec![0; states], 0),
+            |(seen, stamp), candidate| {
+                *stamp += 1;
+                table.find_cycle(candidate.dir, candidate.from, candidate.obstacle, seen, *stamp).is_some()
+            }
+        )
+        .filter(|&cycle| cycle)
+        .count())
+}
+
+pub fn part2_parallel(input: &str) -> usize {
+    try_part2_parallel(input).unwrap_or_else(|e| panic!("{e}"))
+}
+
+/// An obstacle that traps the guard in a loop.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trap {
+    pub obstacle: (usize, usize),
+    /// The turns the guard keeps repeating, as the cell it turns in and the direction it leaves that cell in.
+    pub turns: Vec<((usize, usize), Direction)>
+}
+
+impl Trap {
+    /// The cells of the loop in the order the guard walks them.
+    /// Cells where the loop crosses itself appear more than once.
+    pub fn cells(&self) -> Vec<(usize, usize)> {
+        let mut cells = Vec::new();
+        for (k, &((mut i, mut j), dir)) in self.turns.iter().enumerate() {
+            let (next, _) = self.turns[(k + 1) % self.turns.len()];
+            while (i, j) != next {
+                cells.push((i, j));
+                (i, j) = dir.step((i, j)).unwrap();
+            }
+        }
+        cells
+    }
+}
+
+/// Finds every position where an obstacle traps the guard, ordered by row and column,
+/// together with the loop the guard ends up walking.
+pub fn loop_obstacles(input: &str) -> Result<Vec<Trap>, MapError> {
+    loop_obstacles_with(input, &PatrolRules::default())
+}
+
+/// Finds the obstacles that trap the guard like loop_obstacles, with the guard following the given rules.
+/// 
+/// With wrap-around edges the guard can never leave the map, so it already loops without an obstacle
+/// and the map is rejected with MapError::GuardLoops.
+pub fn loop_obstacles_with(input: &str, rules: &PatrolRules) -> Result<Vec<Trap>, MapError> {
+    let map = read_input(input)?;
+    let candidates = route_candidates(&map, rules)?;
+    let table = JumpTable::new(&map, rules.turn);
+
+    let mut seen = vec![0; map.height * map.width * 4];
+    let mut traps: Vec<Trap> = candidates
+        .into_iter()
+        .zip(1..)
+        .filter_map(|(candidate, stamp)| table
+            .find_cycle(candidate.dir, candidate.from, candidate.obstacle, &mut seen, stamp)
+            .map(|turn| Trap { obstacle: candidate.obstacle, turns: table.cycle_turns(turn, candidate.obstacle) })
+        )
+        .collect();
+
+    traps.sort_by_key(|trap| trap.obstacle);
+    Ok(traps)
+}
+
+/// Draws the loop on the map like the puzzle does, with the obstacle as an O.
+pub fn render_trap(input: &str, trap: &Trap) -> Result<String, MapError> {
+    let map = read_input(input)?;
+
+    let mut directions = vec![vec![[false; 2]; map.width]; map.height];
+    let cells = trap.cells();
+    for (k, &(i, j)) in cells.iter().enumerate() {
+        // A cell is walked vertically or horizontally depending on its neighbors in the loop.
+        for (i_other, j_other) in [cells[(k + 1) % cells.len()], cells[(k + cells.len() - 1) % cells.len()]] {
+            directions[i][j][(i_other == i) as usize] |= (i_other, j_other) != (i, j);
+        }
+    }
+
+    let mut rows: Vec<Vec<char>> = directions
+        .iter()
+        .enumerate()
+        .map(|(i, row)| row
+            .iter()
+            .enumerate()
+            .map(|(j, direction)| if map.blocked(i, j) {
+                '#'
+            } else {
+                match direction {
+                    [true, true] => '+',
+                    [true, false] => '|',
+                    [false, true] => '-',
+                    [false, false] => '.'
+                }
+            })
+            .collect())
+        .collect();
+    for &((i, j), dir) in &map.guards {
+        rows[i][j] = dir.to_char();
+    }
+    rows[trap.obstacle.0][trap.obstacle.1] = 'O';
+
+    Ok(rows
+        .iter()
+        .map(|row| row.iter().collect::<String>())
+        .collect::<Vec<String>>()
+        .join("\n"))
+}
+
+/// The route of a single guard.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GuardPatrol {
+    pub start: (usize, usize),
+    pub dir: Direction,
+    /// The number of distinct cells the guard visits, including the starting one.
+    pub visited: usize,
+    /// Whether the guard ends up walking in a loop instead of leaving the map.
+    pub looped: bool
+}
+
+/// The routes of all guards on the map.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PatrolReport {
+    pub guards: Vec<GuardPatrol>,
+    /// The number of distinct cells visited by any guard.
+    pub visited: usize
+}
+
+/// Moves every guard that is still on the map by one step, in the order of their starting positions.
+/// Guards treat the cells other guards are standing in as obstructions.
+fn tick(map: &Map, rules: &PatrolRules, guards: &mut [Option<((usize, usize), Direction)>]) {
+    for k in 0..guards.len() {
+        let Some(((i, j), dir)) = guards[k] else {
+            continue;
+        };
+
+        guards[k] = match get_movement(map, rules, &dir, i, j) {
+            None => None,
+            Some(Move::Step(i_next, j_next)) if guards
+                .iter()
+                .enumerate()
+                .any(|(other, guard)| other != k && guard.is_some_and(|(position, _)| position == (i_next, j_next)))
+                => Some(((i, j), dir.turn_by(rules.turn))),
+            Some(Move::Step(i_next, j_next)) => Some(((i_next, j_next), dir)),
+            Some(Move::Turn) => Some(((i, j), dir.turn_by(rules.turn)))
+        };
+    }
+}
+
+/// Simulates all guards on the map at the same time under the given rules,
+/// until every guard has left the map or the guards together repeat a state.
+/// 
+/// Guards take their steps one after another in the order of their starting positions,
+/// and turn in front of another guard like they would in front of an obstruction.
+/// The guards that are still on the map when their state repeats have looped.
+pub fn patrol(input: &str, rules: &PatrolRules) -> Result<PatrolReport, MapError> {
+    let map = parse_map(input)?;
+    if map.guards.is_empty() {
+        return Err(MapError::NoGuard);
+    }
+    let (height, width) = (map.height, map.width);
+
+    let mut guards: Vec<Option<((usize, usize), Direction)>> = map.guards.iter().copied().map(Some).collect();
+    let mut seen: Vec<Visited> = map.guards.iter().map(|_| Visited::new(height, width)).collect();
+    let mut visited = vec![0; guards.len()];
+    let mut covered = Visited::new(height, width);
+
+    // The state of all guards together can be far too large to remember every one,
+    // so loops are found with Brent's algorithm, which only keeps a single earlier state.
+    let mut saved = guards.clone();
+    let mut power = 1;
+    let mut length = 0;
+    let looped = loop {
+        for (k, guard) in guards.iter().enumerate() {
+            if let Some(((i, j), dir)) = *guard {
+                if !seen[k].any(i, j) {
+                    visited[k] += 1;
+                }
+                seen[k].set(i, j, dir);
+                covered.set(i, j, dir);
+            }
+        }
+
+        if guards.iter().all(Option::is_none) {
+            break false;
+        }
+
+        tick(&map, rules, &mut guards);
+        length += 1;
+        if guards == saved {
+            break true;
+        }
+        if length == power {
+            saved = guards.clone();
+            power *= 2;
+            length = 0;
+        }
+    };
+
+    let guards = map.guards
+        .iter()
+        .zip(guards)
+        .zip(visited)
+        .map(|((&(start, dir), end), visited)| GuardPatrol { start, dir, visited, looped: looped && end.is_some() })
+        .collect();
+
+    let visited = (0..height)
+        .flat_map(|i| (0..width).map(move |j| (i, j)))
+        .filter(|&(i, j)| covered.any(i, j))
+        .count();
+    Ok(PatrolReport { guards, visited })
 }
 
 #[cfg(test)]
@@ -210,6 +814,8 @@ mod tests {
         let input = fs::read_to_string("input.txt")?;
         dbg!(part1(&input));
         dbg!(part2(&input));
+        dbg!(part2_jump(&input));
+        dbg!(part2_parallel(&input));
         Ok(())
     }
 
@@ -234,5 +840,227 @@ mod tests {
     #[test]
     fn part2_test() {
         assert_eq!(part2(get_input()), 6);
+        assert_eq!(part2_jump(get_input()), 6);
+        assert_eq!(part2_parallel(get_input()), 6);
+    }
+
+    /// Generates maps with random obstructions using a linear congruential generator.
+    fn random_maps(count: usize, height: usize, width: usize) -> Vec<String> {
+        let mut seed: u64 = 2024;
+        let mut next = || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) as usize
+        };
+
+        (0..count)
+            .map(|_| {
+                let mut map: Vec<Vec<char>> = (0..height)
+                    .map(|_| (0..width).map(|_| if next() % 7 == 0 { '#' } else { '.' }).collect())
+                    .collect();
+                map[next() % height][next() % width] = ['>', '^', '<', 'v'][next() % 4];
+                map.iter().map(|row| row.iter().collect::<String>()).collect::<Vec<String>>().join("\n")
+            })
+            // The guard has to leave the map without any obstacles placed.
+            .filter(|input| try_part1(input) != Err(MapError::GuardLoops))
+            .collect()
+    }
+
+    #[test]
+    fn part2_jump_test() {
+        let maps = random_maps(200, 12, 15);
+        assert!(maps.len() > 100);
+        for map in maps {
+            let count = part2(&map);
+            assert_eq!(part2_jump(&map), count, "{map}");
+            assert_eq!(part2_parallel(&map), count, "{map}");
+            assert_eq!(loop_obstacles(&map).unwrap().len(), count, "{map}");
+        }
+    }
+
+    #[test]
+    fn compact_map_test() {
+        let mut map = read_input(get_input()).unwrap();
+        assert_eq!((map.height, map.width, map.obstructions.len()), (10, 10, 2));
+        assert!(map.blocked(0, 4) && map.blocked(9, 6) && !map.blocked(6, 4));
+        map.set_blocked(6, 4, true);
+        map.set_blocked(0, 4, false);
+        assert!(!map.blocked(0, 4) && map.blocked(6, 4));
+
+        // Neighboring cells share a byte without overwriting each other.
+        let mut visited = Visited::new(3, 3);
+        visited.set(0, 0, Direction::Down);
+        visited.set(0, 1, Direction::Right);
+        visited.set(2, 2, Direction::Left);
+        assert!(visited.get(0, 0, Direction::Down) && !visited.get(0, 0, Direction::Right));
+        assert!(visited.get(0, 1, Direction::Right) && !visited.get(0, 1, Direction::Down));
+        assert!(visited.get(2, 2, Direction::Left) && !visited.any(1, 0) && !visited.any(2, 1));
+        visited.unset(0, 1, Direction::Right);
+        assert!(visited.any(0, 0) && !visited.any(0, 1) && visited.any(2, 2));
+        visited.unset(0, 0, Direction::Down);
+        assert!(!visited.any(0, 0) && visited.get(2, 2, Direction::Left));
+    }
+
+    #[test]
+    fn loop_obstacles_test() {
+        let traps = loop_obstacles(get_input()).unwrap();
+        let obstacles: Vec<(usize, usize)> = traps.iter().map(|trap| trap.obstacle).collect();
+        assert_eq!(obstacles, [(6, 3), (7, 6), (7, 7), (8, 1), (8, 3), (9, 7)]);
+
+        assert_eq!(traps[0].turns, [
+            ((6, 4), Direction::Up),
+            ((1, 4), Direction::Right),
+            ((1, 8), Direction::Down),
+            ((6, 8), Direction::Left)
+        ]);
+        assert_eq!(traps[0].cells().len(), 18);
+        assert_eq!(render_trap(get_input(), &traps[0]).unwrap(), "\
+....#.....
+....+---+#
+....|...|.
+..#.|...|.
+....|..#|.
+....|...|.
+.#.O^---+.
+........#.
+#.........
+......#...");
+    }
+
+    #[test]
+    fn patrol_test() {
+        let report = patrol(get_input(), &PatrolRules::default()).unwrap();
+        assert_eq!(report.visited, 41);
+        assert_eq!(report.guards, [GuardPatrol { start: (6, 4), dir: Direction::Up, visited: 41, looped: false }]);
+
+        let left = patrol(get_input(), &PatrolRules { turn: Turn::Left, ..Default::default() }).unwrap();
+        assert!(!left.guards[0].looped);
+        assert_eq!(left.visited, 10);
+
+        // Wrapping around, the guard can never leave the map.
+        let wrap = patrol(get_input(), &PatrolRules { edges: Edges::Wrap, ..Default::default() }).unwrap();
+        assert!(wrap.guards[0].looped);
+        assert!(wrap.visited >= 41);
+
+        for map in random_maps(50, 12, 15) {
+            assert_eq!(patrol(&map, &PatrolRules::default()).unwrap().visited, part1(&map), "{map}");
+        }
+    }
+
+    #[test]
+    fn patrol_guards_test() {
+        let map = "\
+.#....
+>...#.
+......
+.#..<.
+..v...";
+        let report = patrol(map, &PatrolRules::default()).unwrap();
+        assert_eq!(report.guards, [
+            GuardPatrol { start: (1, 0), dir: Direction::Right, visited: 7, looped: false },
+            GuardPatrol { start: (3, 4), dir: Direction::Left, visited: 6, looped: false },
+            GuardPatrol { start: (4, 2), dir: Direction::Down, visited: 1, looped: false }
+        ]);
+        assert_eq!(report.visited, 12);
+
+        // A guard boxed in by obstructions keeps turning on the spot.
+        let boxed = patrol(".#.\n#^#\n.#.", &PatrolRules::default()).unwrap();
+        assert_eq!(boxed.guards[0].visited, 1);
+        assert!(boxed.guards[0].looped);
+
+        // Guards walking towards each other turn away instead of passing.
+        let report = patrol(">..<", &PatrolRules::default()).unwrap();
+        assert_eq!(report.guards, [
+            GuardPatrol { start: (0, 0), dir: Direction::Right, visited: 2, looped: false },
+            GuardPatrol { start: (0, 3), dir: Direction::Left, visited: 2, looped: false }
+        ]);
+        assert_eq!(report.visited, 4);
+
+        // Two guards in a corridor keep turning each other around.
+        let report = patrol("#####\n#>.<#\n#####", &PatrolRules::default()).unwrap();
+        assert!(report.guards.iter().all(|guard| guard.looped));
+        assert_eq!(report.visited, 3);
+
+        // A guard that loops keeps the simulation going after the other guards have left.
+        let report = patrol(".#...\n....#\n#....\n.^.#.\n....<", &PatrolRules::default()).unwrap();
+        assert!(report.guards[0].looped && !report.guards[1].looped);
+    }
+
+    #[test]
+    fn loop_obstacles_with_test() {
+        for turn in [Turn::Left, Turn::Right] {
+            let rules = PatrolRules { turn, ..Default::default() };
+            for map in random_maps(100, 8, 10) {
+                let Ok(traps) = loop_obstacles_with(&map, &rules) else {
+                    continue;
+                };
+
+                // Place an obstacle on every empty cell and simulate the patrol.
+                let expected: Vec<(usize, usize)> = map
+                    .lines()
+                    .enumerate()
+                    .flat_map(|(i, row)| row.char_indices().filter(|&(_, c)| c == '.').map(move |(j, _)| (i, j)))
+                    .filter(|&(i, j)| {
+                        let mut rows: Vec<Vec<char>> = map.lines().map(|row| row.chars().collect()).collect();
+                        rows[i][j] = '#';
+                        let map: Vec<String> = rows.iter().map(|row| row.iter().collect()).collect();
+                        patrol(&map.join("\n"), &rules).unwrap().guards[0].looped
+                    })
+                    .collect();
+                assert_eq!(traps.iter().map(|trap| trap.obstacle).collect::<Vec<_>>(), expected, "{map}");
+            }
+        }
+
+        assert_eq!(loop_obstacles_with(get_input(), &PatrolRules::default()), loop_obstacles(get_input()));
+        assert_eq!(
+            loop_obstacles_with(get_input(), &PatrolRules { edges: Edges::Wrap, ..Default::default() }),
+            Err(MapError::GuardLoops)
+        );
+    }
+
+    #[test]
+    fn map_error_test() {
+        assert_eq!(try_part1(""), Err(MapError::Empty));
+        assert_eq!(try_part1("\n\n"), Err(MapError::Empty));
+        assert_eq!(try_part1("\n..^"), Err(MapError::Ragged { line: 2, len: 3, expected: 0 }));
+        assert_eq!(try_part1("..#\n.x^\n..."), Err(MapError::UnknownSymbol { line: 2, column: 2, symbol: 'x' }));
+        assert_eq!(try_part1("..#\n.^\n..."), Err(MapError::Ragged { line: 2, len: 2, expected: 3 }));
+        assert_eq!(try_part2("..#\n...\n..."), Err(MapError::NoGuard));
+        assert_eq!(try_part2(">.#\n...\n..v"), Err(MapError::MultipleGuards(vec![(1, 1), (3, 3)])));
+        assert_eq!(patrol("..#\n...", &PatrolRules::default()), Err(MapError::NoGuard));
+        assert_eq!(
+            MapError::UnknownSymbol { line: 2, column: 2, symbol: 'x' }.to_string(),
+            "line 2, column 2: unknown symbol 'x'"
+        );
+        assert_eq!(
+            MapError::MultipleGuards(vec![(1, 1), (3, 3)]).to_string(),
+            "the map has 2 guards, at line 1, column 1; line 3, column 3"
+        );
+
+        // Guards that never leave the map are reported instead of walked forever.
+        for map in [".#.\n#^#\n.#.", ".#...\n....#\n#....\n.^.#."] {
+            assert_eq!(try_part1(map), Err(MapError::GuardLoops), "{map}");
+            assert_eq!(try_part2(map), Err(MapError::GuardLoops), "{map}");
+            assert_eq!(try_part2_jump(map), Err(MapError::GuardLoops), "{map}");
+            assert_eq!(try_part2_parallel(map), Err(MapError::GuardLoops), "{map}");
+            assert_eq!(loop_obstacles(map), Err(MapError::GuardLoops), "{map}");
+        }
+    }
+
+    #[test]
+    fn edge_guard_test() {
+        // Guards facing off the map leave it immediately.
+        for map in ["^..\n...", "..>\n...", "...\n<..", "...\n.v.", "^"] {
+            assert_eq!(try_part1(map), Ok(1), "{map}");
+            assert_eq!(try_part2(map), Ok(0), "{map}");
+            assert_eq!(part2_jump(map), 0, "{map}");
+            assert_eq!(loop_obstacles(map), Ok(Vec::new()), "{map}");
+        }
+
+        assert_eq!(try_part1("#..\n^.."), Ok(3));
+        // The only trap is an obstacle on the left edge.
+        let map = ".#...\n....#\n.....\n#..#.\n.^...";
+        assert_eq!(try_part2(map), Ok(1));
+        assert_eq!(part2_parallel(map), 1);
+        assert_eq!(loop_obstacles(map).unwrap()[0].obstacle, (2, 0));
     }
 }